@@ -9,12 +9,27 @@ use std::path::Path;
 struct IDCertTable {
     // ID -> Cert Bytes
     data: HashMap<String, String>,
+    list_date: String,
+    #[serde(default = "default_version")]
+    version: u32,
+    /* Identifiers which still decompress but are no longer used for compression */
+    #[serde(default)]
+    retired: Vec<String>,
 }
 
-fn hash(bytes: &[u8]) -> Vec<u8> {
-    let mut result_256 = [0; 32];
-    blake::hash(256, bytes, &mut result_256).expect("Error hashing");
-    result_256.to_vec()
+fn default_version() -> u32 {
+    1
+}
+
+/* Must match `index_key` in src/pass1/mod.rs */
+const INDEX_SUFFIX_LEN: usize = 8;
+
+fn index_key(cert: &[u8]) -> Vec<u8> {
+    let mut key = vec![0; 3 + INDEX_SUFFIX_LEN];
+    key[..3].copy_from_slice(&(cert.len() as u32).to_be_bytes()[1..]);
+    let suffix = &cert[cert.len().saturating_sub(INDEX_SUFFIX_LEN)..];
+    key[3 + INDEX_SUFFIX_LEN - suffix.len()..].copy_from_slice(suffix);
+    key
 }
 
 fn wrapper(x: &str) -> String {
@@ -29,55 +44,187 @@ fn wrapper(x: &str) -> String {
     output
 }
 
-fn load_builtin_cert_mappings() -> impl Iterator<Item = (String, String, Vec<u8>)> {
+struct Tlv<'a> {
+    tag: u8,
+    whole: &'a [u8],
+    contents: &'a [u8],
+    rest: &'a [u8],
+}
+
+/* Splits a DER TLV off the front of `bytes` */
+fn der_split(bytes: &[u8]) -> Option<Tlv<'_>> {
+    let tag = *bytes.first()?;
+    let first_len = *bytes.get(1)? as usize;
+    let (header_len, len) = if first_len < 0x80 {
+        (2, first_len)
+    } else {
+        let len_bytes = first_len & 0x7f;
+        if len_bytes == 0 || len_bytes > 3 {
+            return None;
+        }
+        let len = bytes
+            .get(2..2 + len_bytes)?
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | *b as usize);
+        (2 + len_bytes, len)
+    };
+    let end = header_len.checked_add(len)?;
+    let tlv = bytes.get(..end)?;
+    Some(Tlv {
+        tag,
+        whole: tlv,
+        contents: &tlv[header_len..],
+        rest: &bytes[end..],
+    })
+}
+
+/* Returns the subject Name and, if present, the subject key identifier extension of a certificate */
+fn subject_and_key_id(cert: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    const SKI_OID: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x0e];
+    let cert = der_split(cert)?.contents;
+    let mut tbs = der_split(cert)?.contents;
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_split(tbs)?.rest;
+    }
+    /* serialNumber, signature, issuer, validity */
+    for _ in 0..4 {
+        tbs = der_split(tbs)?.rest;
+    }
+    let subject = der_split(tbs)?;
+    let mut rest = subject.rest;
+    let mut key_id = None;
+    while let Some(field) = der_split(rest) {
+        rest = field.rest;
+        if field.tag != 0xa3 {
+            continue;
+        }
+        let mut extensions = der_split(field.contents)?.contents;
+        while let Some(extension) = der_split(extensions) {
+            extensions = extension.rest;
+            if let Some(value) = extension.contents.strip_prefix(SKI_OID) {
+                let value = der_split(value)?.contents;
+                key_id = Some(der_split(value)?.contents.to_vec());
+            }
+        }
+    }
+    Some((subject.whole.to_vec(), key_id))
+}
+
+/* The builtin pass 2 dictionary holds the fields an end-entity certificate copies from its issuer:
+the issuer Name and the authority key identifier extension. Newer CAs are placed last, where
+Brotli's backward references are cheapest, and the oldest are dropped to respect the size bound. */
+fn build_pass2_dictionary(certs: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    const MAX_DICTIONARY_SIZE: usize = 128 * 1024;
+    const AKI_PREFIX: &[u8] = &[
+        0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14,
+    ];
+    let mut chunks = Vec::new();
+    for (_, cert) in certs {
+        if let Some((subject, key_id)) = subject_and_key_id(cert) {
+            let mut chunk = Vec::new();
+            if let Some(key_id) = key_id.filter(|x| x.len() == 20) {
+                chunk.extend_from_slice(AKI_PREFIX);
+                chunk.extend_from_slice(&key_id);
+            }
+            chunk.extend_from_slice(&subject);
+            chunks.push(chunk);
+        }
+    }
+    let mut size = 0;
+    let keep = chunks
+        .iter()
+        .rev()
+        .take_while(|x| {
+            size += x.len();
+            size <= MAX_DICTIONARY_SIZE
+        })
+        .count();
+    chunks[chunks.len() - keep..].concat()
+}
+
+fn load_builtin_cert_mappings() -> (IDCertTable, impl Iterator<Item = (String, String)>) {
     let json_path = Path::new("data/").join("pass1.json");
+    println!("cargo:rerun-if-changed={}", json_path.display());
     let mut file = File::open(json_path).expect("Failed to open file");
     let mut content = String::new();
     file.read_to_string(&mut content)
         .expect("Failed to read file");
-    let table: IDCertTable = serde_json::from_str(&content).expect("Failed to deserialize JSON");
-    table
-        .data
-        .into_iter()
-        .map(|(x, y)| (x, y.clone(), hash(&hex::decode(y).expect("Hex error"))))
+    let mut table: IDCertTable = serde_json::from_str(&content).expect("Failed to deserialize JSON");
+    let mappings = std::mem::take(&mut table.data).into_iter();
+    (table, mappings)
 }
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
     let mut id_to_cert = phf_codegen::Map::<&[u8]>::new();
-    let mut hash_to_id = phf_codegen::Map::<&[u8]>::new();
-    let mut id_to_hash = phf_codegen::Map::<&[u8]>::new();
+    let mut index: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
 
-    for (id, cert, fingerprint) in load_builtin_cert_mappings() {
-        dbg!(&fingerprint);
+    let (table, mappings) = load_builtin_cert_mappings();
+    let mut retired = Vec::new();
+    let mut ordered_certs = Vec::new();
+    for (id, cert) in mappings {
+        let cert_bytes = hex::decode(&cert).expect("Hex Error");
         id_to_cert.entry(
             hex::decode(id.clone()).expect("Hex Error").leak(),
             &wrapper(&cert),
         );
-        hash_to_id.entry(fingerprint.clone().leak(), &wrapper(&id));
-        id_to_hash.entry(hex::decode(id.clone()).expect("Hex Error").leak(), &wrapper(&hex::encode(fingerprint)));
+        if table.retired.iter().any(|x| x.eq_ignore_ascii_case(&id)) {
+            retired.push(wrapper(&id));
+        } else {
+            index.entry(index_key(&cert_bytes)).or_default().push(wrapper(&id));
+        }
+        ordered_certs.push((hex::decode(&id).expect("Hex Error"), cert_bytes));
+    }
+    let mut cert_index = phf_codegen::Map::<&[u8]>::new();
+    for (key, mut ids) in index {
+        ids.sort();
+        cert_index.entry(key.leak(), &format!("&[{}]", ids.join(", ")));
     }
 
-    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("builtin_tables.rs");
+    ordered_certs.sort();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    /* A dictionary produced by the train_pass2 binary replaces the generated one only when asked
+    for, so that a stray file can't change the builtin dictionary and its id */
+    let pass2_dictionary = if env::var_os("CARGO_FEATURE_TRAINED_PASS2_DICTIONARY").is_some() {
+        let trained_path = Path::new("data/").join("pass2_dictionary.bin");
+        println!("cargo:rerun-if-changed={}", trained_path.display());
+        std::fs::read(&trained_path)
+            .expect("The trained-pass2-dictionary feature needs data/pass2_dictionary.bin")
+    } else {
+        build_pass2_dictionary(&ordered_certs)
+    };
+    std::fs::write(Path::new(&out_dir).join("pass2_dictionary.bin"), pass2_dictionary).unwrap();
+
+    let path = Path::new(&out_dir).join("builtin_tables.rs");
     let mut file = BufWriter::new(File::create(path).unwrap());
 
     writeln!(
         &mut file,
-        "static ID_TO_CERT: phf::Map<&'static [u8], &'static [u8]> = \n{};\n",
-        id_to_cert.build()
+        "static LIST_DATE: &str = {:?};\n",
+        table.list_date
     )
     .unwrap();
 
+    writeln!(&mut file, "static VERSION: u32 = {};\n", table.version).unwrap();
+
     writeln!(
         &mut file,
-        "static ID_TO_HASH: phf::Map<&'static [u8], &'static [u8]> = \n{};\n",
-        id_to_hash.build()
+        "#[allow(dead_code)]\nstatic RETIRED: &[&[u8]] = &[{}];\n",
+        retired.join(", ")
+    )
+    .unwrap();
+
+    writeln!(
+        &mut file,
+        "static ID_TO_CERT: phf::Map<&'static [u8], &'static [u8]> = \n{};\n",
+        id_to_cert.build()
     )
     .unwrap();
 
     writeln!(
         &mut file,
-        "static HASH_TO_ID: phf::Map<&'static [u8], &'static [u8]> = \n{};\n",
-        hash_to_id.build()
+        "static CERT_INDEX: phf::Map<&'static [u8], &'static [&'static [u8]]> = \n{};\n",
+        cert_index.build()
     )
     .unwrap();
 }
@@ -1,18 +1,27 @@
 #![no_main]
 
 use libfuzzer_sys::fuzz_target;
-use abridged_certs::tls;
-use bytes::Bytes;
 
 fuzz_target!(|data: &[u8]| {
-    let mut b = Bytes::copy_from_slice(data);
-    let cm = abridged_certs::tls::CertificateMessage::read_from_bytes(&mut b.clone());
+    let cm = abridged_certs::tls::CertificateMessage::read_from_bytes(data);
     if let Ok(cm) = cm {
+        assert_eq!(cm.encoded_len(), data.len());
         let msg_bytes : Vec<u8> = Vec::new();
         let mut cursor = std::io::Cursor::new(msg_bytes);
         cm.write_to_bytes(&mut cursor).expect("No errors");
         let msg_bytes = cursor.into_inner();
-        /* Ok to have some trailing data, since could be multiple messages */
-        assert_eq!(msg_bytes, b[..msg_bytes.len()]);
+        /* Parsing rejects trailing data, so the encoding must match exactly */
+        assert_eq!(msg_bytes, data);
+        for entry in &cm.certificate_entries {
+            if let Ok(extensions) = entry.parse_extensions() {
+                let encoded = abridged_certs::tls::encode_extensions(&extensions).expect("No errors");
+                assert_eq!(encoded, entry.extensions);
+                for extension in &extensions {
+                    if let Ok(value) = extension.decode() {
+                        assert_eq!(value.to_vec().expect("No errors"), extension.data);
+                    }
+                }
+            }
+        }
     }
 });
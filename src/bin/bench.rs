@@ -1,5 +1,234 @@
-extern crate abridged_certs;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use abridged_certs::pass2::{Codec, Dictionary};
+use abridged_certs::Builder;
+use clap::Parser;
+use rustls::compress::CompressionLevel;
+use serde::Serialize;
+
+/// Measures each compression scheme over a corpus of Certificate messages.
+///
+/// A table is written to stderr and a JSON summary to stdout, so that results can be recorded
+/// with `cargo run --release --features bench --bin bench > bench.json`.
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Directory of Certificate messages, one per file.
+    #[clap(default_value = "data/certificate_messages")]
+    corpus : PathBuf,
+    /// How many times each message is compressed and decompressed.
+    #[clap(long, short, default_value_t = 10)]
+    iterations : usize,
+    /// Compress at `CompressionLevel::Interactive` rather than `Amortized`.
+    #[clap(long, action)]
+    interactive : bool,
+}
+
+type CompressFn = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+type DecompressFn = Box<dyn Fn(&[u8], u32) -> Vec<u8>>;
+
+struct Scheme {
+    name: &'static str,
+    compress: CompressFn,
+    decompress: DecompressFn,
+}
+
+impl Scheme {
+    fn abridged(name: &'static str, builder: Builder, level: CompressionLevel) -> Self {
+        let c = builder.build_compressor().expect("Invalid configuration");
+        let d = builder.build_decompressor().expect("Invalid configuration");
+        Scheme {
+            name,
+            compress: Box::new(move |msg| c.compress_to_bytes_with_level(msg, level).expect("Error compressing")),
+            decompress: Box::new(move |msg, max_size| d.decompress_to_bytes(msg, max_size).expect("Error decompressing")),
+        }
+    }
+
+    /* RFC 8879 zlib, which rustls does not implement */
+    fn zlib() -> Self {
+        Scheme {
+            name: "zlib",
+            compress: Box::new(|msg| {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(msg).expect("Error compressing");
+                encoder.finish().expect("Error compressing")
+            }),
+            decompress: Box::new(|msg, max_size| {
+                let mut output = Vec::with_capacity(max_size as usize);
+                flate2::read::ZlibDecoder::new(msg)
+                    .take(max_size as u64)
+                    .read_to_end(&mut output)
+                    .expect("Error decompressing");
+                output
+            }),
+        }
+    }
+}
+
+/// Per-message latencies in microseconds.
+#[derive(Serialize, Debug)]
+struct Timing {
+    mb_per_s: f64,
+    p50_us: f64,
+    p90_us: f64,
+    p99_us: f64,
+}
+
+impl Timing {
+    fn new(mut samples: Vec<Duration>, bytes: usize) -> Self {
+        samples.sort();
+        let total: Duration = samples.iter().sum();
+        let percentile = |p: f64| {
+            let index = ((samples.len() - 1) as f64 * p).round() as usize;
+            samples[index].as_secs_f64() * 1e6
+        };
+        Timing {
+            mb_per_s: bytes as f64 / total.as_secs_f64() / 1e6,
+            p50_us: percentile(0.5),
+            p90_us: percentile(0.9),
+            p99_us: percentile(0.99),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SchemeResult {
+    scheme: &'static str,
+    input_bytes: usize,
+    output_bytes: usize,
+    /// Output size as a fraction of the input size.
+    ratio: f64,
+    compress: Timing,
+    decompress: Timing,
+}
+
+#[derive(Serialize, Debug)]
+struct Summary {
+    crate_version: &'static str,
+    corpus: String,
+    messages: usize,
+    iterations: usize,
+    level: &'static str,
+    results: Vec<SchemeResult>,
+}
+
+fn run(scheme: &Scheme, messages: &[Vec<u8>], iterations: usize) -> SchemeResult {
+    /* An untimed pass checks the round trip and warms up caches */
+    let mut output_bytes = 0;
+    let compressed: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|msg| {
+            let out = (scheme.compress)(msg);
+            assert_eq!(&(scheme.decompress)(&out, msg.len() as u32), msg, "{} failed to round trip", scheme.name);
+            output_bytes += out.len();
+            out
+        })
+        .collect();
+    let input_bytes: usize = messages.iter().map(Vec::len).sum();
+
+    let mut compress_samples = Vec::with_capacity(messages.len() * iterations);
+    let mut decompress_samples = Vec::with_capacity(messages.len() * iterations);
+    for _ in 0..iterations {
+        for (msg, out) in messages.iter().zip(&compressed) {
+            let start = Instant::now();
+            std::hint::black_box((scheme.compress)(std::hint::black_box(msg)));
+            compress_samples.push(start.elapsed());
+            let start = Instant::now();
+            std::hint::black_box((scheme.decompress)(std::hint::black_box(out), msg.len() as u32));
+            decompress_samples.push(start.elapsed());
+        }
+    }
+    SchemeResult {
+        scheme: scheme.name,
+        input_bytes,
+        output_bytes,
+        ratio: output_bytes as f64 / input_bytes as f64,
+        compress: Timing::new(compress_samples, input_bytes * iterations),
+        decompress: Timing::new(decompress_samples, input_bytes * iterations),
+    }
+}
 
 fn main() {
-    todo!("Implement a simple benchmark")
+    let args = Cli::parse();
+    assert!(args.iterations > 0, "At least one iteration is needed");
+    let level = if args.interactive {
+        CompressionLevel::Interactive
+    } else {
+        CompressionLevel::Amortized
+    };
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&args.corpus)
+        .expect("Failed to read corpus")
+        .map(|x| x.unwrap().path())
+        .collect();
+    paths.sort();
+    let mut messages = Vec::new();
+    let pass1_only = Builder::new().without_pass2().build_compressor().unwrap();
+    for path in paths {
+        let input = fs::read(&path).expect("Failed to open input");
+        match pass1_only.compress_to_bytes(&input) {
+            Ok(_) => messages.push(input),
+            Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    assert!(!messages.is_empty(), "No Certificate messages in {}", args.corpus.display());
+
+    let schemes = [
+        Scheme::abridged("pass1", Builder::new().without_pass2(), level),
+        Scheme::abridged("pass1+brotli", Builder::new().without_pass2_dictionary(), level),
+        Scheme::abridged("pass1+brotli+dict", Builder::new(), level),
+        Scheme::abridged(
+            "pass1+zstd+dict",
+            Builder::new()
+                .pass2_codec(Codec::zstd())
+                .pass2_dictionary(Dictionary::builtin()),
+            level,
+        ),
+        Scheme::zlib(),
+        Scheme::abridged("brotli", Builder::new().without_pass1().without_pass2_dictionary(), level),
+        Scheme::abridged(
+            "zstd",
+            Builder::new()
+                .without_pass1()
+                .pass2_codec(Codec::zstd())
+                .without_pass2_dictionary(),
+            level,
+        ),
+    ];
+
+    let results: Vec<SchemeResult> = schemes
+        .iter()
+        .map(|scheme| run(scheme, &messages, args.iterations))
+        .collect();
+
+    eprintln!(
+        "{:<18} {:>9} {:>7} {:>10} {:>9} {:>9} {:>10} {:>9} {:>9}",
+        "scheme", "bytes", "ratio", "comp MB/s", "p50 us", "p99 us", "dec MB/s", "p50 us", "p99 us"
+    );
+    for r in &results {
+        eprintln!(
+            "{:<18} {:>9} {:>7.3} {:>10.1} {:>9.1} {:>9.1} {:>10.1} {:>9.1} {:>9.1}",
+            r.scheme,
+            r.output_bytes,
+            r.ratio,
+            r.compress.mb_per_s,
+            r.compress.p50_us,
+            r.compress.p99_us,
+            r.decompress.mb_per_s,
+            r.decompress.p50_us,
+            r.decompress.p99_us
+        );
+    }
+
+    let summary = Summary {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        corpus: args.corpus.display().to_string(),
+        messages: messages.len(),
+        iterations: args.iterations,
+        level: if args.interactive { "interactive" } else { "amortized" },
+        results,
+    };
+    println!("{}", serde_json::to_string_pretty(&summary).expect("Error serializing summary"));
 }
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::PathBuf;
+
+use abridged_certs::ccadb;
+use abridged_certs::pass1::LoadedDictionary;
+use clap::Parser;
+
+/// Builds a map from identifiers to WebPKI Intermediate and Root Certificates
+#[derive(Parser, Debug)]
+struct Cli {
+    /// A CCADB WebTrustListAsOf CSV export
+    input : PathBuf,
+    /// The date the export was taken as-of (YYYY-MM-DD)
+    #[clap(long, short)]
+    date : String,
+    #[clap(long, short, default_value = "pass1.json")]
+    output : PathBuf,
+    #[clap(long, short, default_value = "listing.md")]
+    listing : PathBuf,
+    /// A previously published dictionary. Its identifiers are kept and only new certificates are allocated
+    #[clap(long, short)]
+    previous : Option<PathBuf>,
+    /// Where to write the changes relative to --previous as JSON
+    #[clap(long, requires = "previous")]
+    diff : Option<PathBuf>,
+}
+
+fn main() {
+    let args = Cli::parse();
+    let csv = fs::File::open(&args.input).expect("Failed to open input");
+    let records = ccadb::parse_webtrust_csv(csv).expect("Failed to parse CCADB export");
+    eprintln!("Read {} certificates", records.len());
+    let dictionary = match &args.previous {
+        Some(previous) => {
+            let previous = LoadedDictionary::from_file(previous).expect("Failed to load previous dictionary");
+            let (dictionary, diff) =
+                ccadb::update_cert_dict(&previous, records, &args.date).expect("Failed to update dictionary");
+            eprintln!(
+                "Version {} -> {}: {} added, {} retired, {} reinstated",
+                diff.from_version,
+                diff.to_version,
+                diff.added.len(),
+                diff.retired.len(),
+                diff.reinstated.len()
+            );
+            if let Some(path) = &args.diff {
+                fs::write(path, diff.to_json()).expect("Failed to write diff");
+            }
+            dictionary
+        }
+        None => ccadb::create_cert_dict(records, &args.date).expect("Failed to build dictionary"),
+    };
+    fs::write(&args.output, dictionary.to_json()).expect("Failed to write dictionary");
+    fs::write(&args.listing, ccadb::markdown_listing(&dictionary)).expect("Failed to write listing");
+    eprintln!("Output {} entries to {} and {}", dictionary.len(), args.output.display(), args.listing.display());
+}
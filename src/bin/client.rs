@@ -1,44 +1,84 @@
+use std::error::Error as StdError;
 use std::io::{stdout, Read, Write};
 use std::net::TcpStream;
-use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use clap::Parser;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::RootCertStore;
-use lazy_static::lazy_static;
 
-lazy_static! {
-    static ref DECOMPRESSOR : abridged_certs::Decompressor = abridged_certs::Decompressor::new_from_builtin();
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Additional pass 1 dictionary versions to advertise as CODEPOINT=PATH, in order of preference.
+    dictionaries : Vec<String>,
+    #[clap(long, default_value = "www.rust-lang.org:443")]
+    connect : String,
+    /// The name the server's certificate must match. Defaults to the host in --connect.
+    #[clap(long)]
+    server_name : Option<String>,
+    /// Trust the CAs in this PEM file instead of the webpki roots.
+    #[clap(long)]
+    ca : Option<PathBuf>,
+    /// PEM file holding a certificate chain to authenticate with.
+    #[clap(long, requires = "key")]
+    cert : Option<PathBuf>,
+    /// PEM file holding the private key for --cert.
+    #[clap(long, requires = "cert")]
+    key : Option<PathBuf>,
 }
 
 //Complied from SimpleClient
-fn main() {
-    let root_store = RootCertStore {
-        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+fn main() -> Result<(), Box<dyn StdError>> {
+    let args = Cli::parse();
+    let host = args.connect.rsplit_once(':').map_or(args.connect.as_str(), |(host, _)| host);
+    let server_name = args.server_name.clone().unwrap_or_else(|| host.to_string());
+
+    let root_store = match &args.ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)? {
+                roots.add(cert?)?;
+            }
+            roots
+        }
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+        },
+    };
+    let config = rustls::ClientConfig::builder().with_root_certificates(root_store);
+    let mut config = match (&args.cert, &args.key) {
+        (Some(cert), Some(key)) => config.with_client_auth_cert(
+            CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?,
+            PrivateKeyDer::from_pem_file(key)?,
+        )?,
+        _ => config.with_no_client_auth(),
     };
-    let mut config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
 
-    config.cert_decompressors = vec![(DECOMPRESSOR.deref())];
+    /* Compressors are only used for our own certificate, when the server asks for one */
+    let builders = abridged_certs::dictionary_builders(&args.dictionaries)?;
+    let (compressors, decompressors) = abridged_certs::static_codecs(&builders)?;
+    config.cert_compressors = compressors;
+    config.cert_decompressors = decompressors;
 
     // Allow using SSLKEYLOGFILE.
     config.key_log = Arc::new(rustls::KeyLogFile::new());
 
-    let server_name = "www.rust-lang.org".try_into().unwrap();
-    let mut conn = rustls::ClientConnection::new(Arc::new(config), server_name).unwrap();
-    let mut sock = TcpStream::connect("www.rust-lang.org:443").unwrap();
+    let mut conn = rustls::ClientConnection::new(Arc::new(config), server_name.clone().try_into()?)?;
+    let mut sock = TcpStream::connect(&args.connect)?;
     let mut tls = rustls::Stream::new(&mut conn, &mut sock);
-    tls.write_all(
+    let request = format!(
         concat!(
             "GET / HTTP/1.1\r\n",
-            "Host: www.rust-lang.org\r\n",
+            "Host: {}\r\n",
             "Connection: close\r\n",
             "Accept-Encoding: identity\r\n",
             "\r\n"
-        )
-        .as_bytes(),
-    )
-    .unwrap();
+        ),
+        server_name
+    );
+    tls.write_all(request.as_bytes())?;
     let ciphersuite = tls
         .conn
         .negotiated_cipher_suite()
@@ -47,9 +87,9 @@ fn main() {
         &mut std::io::stderr(),
         "Current ciphersuite: {:?}",
         ciphersuite.suite()
-    )
-    .unwrap();
+    )?;
     let mut plaintext = Vec::new();
-    tls.read_to_end(&mut plaintext).unwrap();
-    stdout().write_all(&plaintext).unwrap();
-}
\ No newline at end of file
+    tls.read_to_end(&mut plaintext)?;
+    stdout().write_all(&plaintext)?;
+    Ok(())
+}
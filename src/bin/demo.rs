@@ -1,6 +1,8 @@
 use std::{fs, path::PathBuf};
 
-use abridged_certs;
+use abridged_certs::frame::FrameHeader;
+use abridged_certs::pass2::Dictionary;
+use abridged_certs::Builder;
 use clap::Parser;
 use base64::prelude::*;
 
@@ -10,16 +12,35 @@ struct Cli {
     decompress : bool,
     #[clap(long, short, action)]
     base64 : bool,
+    /// Read or write messages with a header describing how they were compressed
+    #[clap(long, short, action)]
+    framed : bool,
     input_file : PathBuf,
 }
 
+/* Configures the pipeline a framed message asks for, from the builtin dictionaries */
+fn framed_builder(header : &FrameHeader) -> Builder {
+    let mut builder = Builder::new().ocsp_staples(header.ocsp_staples).integrity_check(header.integrity_check);
+    if header.pass1_version.is_none() {
+        builder = builder.without_pass1();
+    }
+    builder = match header.codec {
+        Some(codec) => builder.pass2_codec(codec),
+        None => builder.without_pass2(),
+    };
+    if header.pass2_dictionary == Some(Dictionary::builtin().id()) {
+        builder = builder.pass2_dictionary(Dictionary::builtin());
+    }
+    builder
+}
+
 fn main() {
     let args = Cli::parse();
-    let mut files : Vec<PathBuf>;
+    let files : Vec<PathBuf>;
     if args.input_file.is_file() {
         files = vec![args.input_file];
     } else if args.input_file.is_dir() {
-        files = fs::read_dir(args.input_file).unwrap().into_iter().map(|x| PathBuf::from(x.unwrap().path())).collect();
+        files = fs::read_dir(args.input_file).unwrap().map(|x| x.unwrap().path()).collect();
     } else {
         panic!("Invalid path");
     }
@@ -28,14 +49,21 @@ fn main() {
         if args.base64 {
             input = BASE64_STANDARD.decode(input).expect("Error decoding base64");
         }
-        let mut output : Vec<u8>;
-        if args.decompress {
+        let output : Vec<u8> = if args.decompress && args.framed {
+            let (header, _) = FrameHeader::read(&input).expect("Error reading frame header");
+            eprintln!("{:?}", header);
+            let decomp = framed_builder(&header).build_decompressor().expect("Unsupported frame");
+            decomp.decompress_framed(&input, 16_000).expect("Error decompressing")
+        } else if args.framed {
+            let comp = abridged_certs::Compressor::new_from_builtin();
+            comp.compress_framed(&input).expect("Error compressing")
+        } else if args.decompress {
             let decomp = abridged_certs::Decompressor::new_from_builtin();
-            output = decomp.decompress_to_bytes(&input, 16_000).expect("Error decompressing");
+            decomp.decompress_to_bytes(&input, 16_000).expect("Error decompressing")
         } else {
             let comp = abridged_certs::Compressor::new_from_builtin();
-            output = comp.compress_to_bytes(&input).expect("Error compressing");
-        }
+            comp.compress_to_bytes(&input).expect("Error compressing")
+        };
         eprintln!("{} from {} to {} bytes", if args.decompress { "Decompressed"} else {"Compressed"} , input.len(), output.len());
         let output = BASE64_STANDARD.encode(output);
         print!("{}",output);
@@ -1,52 +1,70 @@
-use std::env;
 use std::error::Error as StdError;
 use std::io::{Read, Write};
 use std::net::TcpListener;
-use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use clap::Parser;
 use rustls::pki_types::pem::PemObject;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use lazy_static::lazy_static;
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
 
-lazy_static! {
-    static ref COMPRESSOR : abridged_certs::Compressor = abridged_certs::Compressor::new_from_builtin();
+#[derive(Parser, Debug)]
+struct Cli {
+    /// PEM file holding the certificate chain to serve.
+    cert_file : PathBuf,
+    /// PEM file holding the certificate's private key.
+    private_key_file : PathBuf,
+    /// Additional pass 1 dictionary versions as CODEPOINT=PATH, in order of preference.
+    dictionaries : Vec<String>,
+    /// Require a client certificate issued by one of the CAs in this PEM file.
+    #[clap(long)]
+    client_ca : Option<PathBuf>,
+    #[clap(long, default_value_t = 4443)]
+    port : u16,
 }
 
-
 fn main() -> Result<(), Box<dyn StdError>> {
-    let mut args = env::args();
-    args.next();
-    let cert_file = args
-        .next()
-        .expect("missing certificate file argument");
-    let private_key_file = args
-        .next()
-        .expect("missing private key file argument");
-
-    let certs = CertificateDer::pem_file_iter(cert_file)
-        .unwrap()
-        .map(|cert| cert.unwrap())
-        .collect();
-    let private_key = PrivateKeyDer::from_pem_file(private_key_file).unwrap();
-    let mut config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, private_key)?;
-
-    config.cert_compressors = vec![(COMPRESSOR.deref())];
-
-    let listener = TcpListener::bind(format!("[::]:{}", 4443)).unwrap();
+    let args = Cli::parse();
+
+    let certs = CertificateDer::pem_file_iter(&args.cert_file)?.collect::<Result<Vec<_>, _>>()?;
+    let private_key = PrivateKeyDer::from_pem_file(&args.private_key_file)?;
+    let config = rustls::ServerConfig::builder();
+    let config = match &args.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)? {
+                roots.add(cert?)?;
+            }
+            config.with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => config.with_no_client_auth(),
+    };
+    let mut config = config.with_single_cert(certs, private_key)?;
+
+    /* Decompressors are only used for client certificates */
+    let builders = abridged_certs::dictionary_builders(&args.dictionaries)?;
+    let (compressors, decompressors) = abridged_certs::static_codecs(&builders)?;
+    config.cert_compressors = compressors;
+    config.cert_decompressors = decompressors;
+
+    let listener = TcpListener::bind(format!("[::]:{}", args.port)).unwrap();
     let (mut stream, _) = listener.accept()?;
 
     let mut conn = rustls::ServerConnection::new(Arc::new(config))?;
     conn.complete_io(&mut stream)?;
+    if let Some(chain) = conn.peer_certificates() {
+        println!("Client authenticated with {} certificates", chain.len());
+    }
 
-    conn.writer()
-        .write_all(b"Hello from the server")?;
-    conn.complete_io(&mut stream)?;
-    let mut buf = [0; 64];
-    let len = conn.reader().read(&mut buf)?;
-    println!("Received message from client: {:?}", &buf[..len]);
+    let mut tls = rustls::Stream::new(&mut conn, &mut stream);
+    tls.write_all(b"Hello from the server")?;
+    let mut buf = [0; 4096];
+    let len = tls.read(&mut buf)?;
+    println!("Received message from client: {:?}", String::from_utf8_lossy(&buf[..len]));
+    tls.conn.send_close_notify();
+    tls.flush()?;
 
     Ok(())
-}
\ No newline at end of file
+}
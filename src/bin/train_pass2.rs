@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use abridged_certs::{pass1, pass2};
+use clap::Parser;
+
+/// Trains a pass 2 dictionary over a corpus of Certificate messages.
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Directory of Certificate messages, one per file, named after the site they came from.
+    #[clap(long, default_value = "data/certificate_messages")]
+    corpus : PathBuf,
+    /// Tranco list (rank,domain) used to weight each site.
+    #[clap(long)]
+    tranco : Option<PathBuf>,
+    /// Pass 1 dictionary to apply before extracting residuals. Defaults to the builtin one.
+    #[clap(long)]
+    pass1_dictionary : Option<PathBuf>,
+    #[clap(long, default_value_t = 16 * 1024)]
+    max_size : usize,
+    /// Where to write the raw dictionary. Place it at data/pass2_dictionary.bin and build with
+    /// the trained-pass2-dictionary feature to embed it as the builtin.
+    #[clap(long, short, default_value = "pass2_dictionary.bin")]
+    output : PathBuf,
+}
+
+fn load_tranco(path: &PathBuf) -> HashMap<String, u32> {
+    let contents = fs::read_to_string(path).expect("Failed to read Tranco list");
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (rank, domain) = line.trim().split_once(',')?;
+            Some((domain.to_string(), rank.parse().ok()?))
+        })
+        .collect()
+}
+
+fn main() {
+    let args = Cli::parse();
+    let p1 = match &args.pass1_dictionary {
+        Some(path) => pass1::Compressor::new(std::sync::Arc::new(
+            pass1::LoadedDictionary::from_file(path).expect("Failed to load pass 1 dictionary"),
+        )),
+        None => pass1::Compressor::new_builtin(),
+    };
+    let ranks = args.tranco.as_ref().map(load_tranco);
+
+    let mut trainer = pass2::Trainer::new();
+    let mut messages = Vec::new();
+    for entry in fs::read_dir(&args.corpus).expect("Failed to read corpus") {
+        let path = entry.unwrap().path();
+        let input = fs::read(&path).expect("Failed to open input");
+        let site = path.file_name().unwrap().to_string_lossy().to_string();
+        let weight = match &ranks {
+            Some(ranks) => pass2::weight_for_rank(ranks.get(&site).copied()),
+            None => 1.0,
+        };
+        match trainer.add_certificate_message(&p1, &input, weight) {
+            Ok(()) => messages.push(input),
+            Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    eprintln!("Training on {} residual certificates from {} messages", trainer.num_samples(), messages.len());
+
+    let dictionary = trainer.train(args.max_size);
+    fs::write(&args.output, &dictionary).expect("Failed to write dictionary");
+
+    let without = pass2::Compressor::new();
+    let with = pass2::Compressor::new_with_dictionary(pass2::Dictionary::new(dictionary.clone()));
+    let (mut before, mut after) = (0, 0);
+    for msg in &messages {
+        let p1_out = p1.compress_to_bytes(msg).expect("Error compressing");
+        before += without.compress_to_bytes(&p1_out).expect("Error compressing").len();
+        after += with.compress_to_bytes(&p1_out).expect("Error compressing").len();
+    }
+    eprintln!(
+        "Wrote {} bytes to {}. Corpus compresses to {} bytes without it and {} bytes with it",
+        dictionary.len(),
+        args.output.display(),
+        before,
+        after
+    );
+}
@@ -0,0 +1,455 @@
+use crate::pass1::{self, BuiltinDictionary, CertDictionary, DictionaryMetadata};
+use crate::pass2::{self, Codec, Dictionary, Format, DICTIONARY_ID_LEN};
+use crate::sct::{self, LogDictionary, LOG_DICTIONARY_ID_LEN};
+use crate::{Compressor, Decompressor, Error, DEFAULT_CODEPOINT};
+use rustls::CertificateCompressionAlgorithm;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+struct Pass2Config {
+    codec: Codec,
+    interactive_codec: Option<Codec>,
+    dictionary: Option<Dictionary>,
+}
+
+/// Configures a [`Compressor`] and [`Decompressor`] from the same settings.
+///
+/// By default both passes are enabled, pass 1 uses the builtin dictionary and pass 2 uses the
+/// default codec with the builtin pass 2 dictionary, matching `new_from_builtin`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    pass1: Option<Arc<dyn CertDictionary>>,
+    ocsp_staples: bool,
+    strict: bool,
+    sct: Option<Arc<LogDictionary>>,
+    pass2: Option<Pass2Config>,
+    codepoint: u16,
+    max_decompressed_size: Option<u32>,
+    integrity_check: bool,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder {
+            pass1: Some(Arc::new(BuiltinDictionary)),
+            ocsp_staples: false,
+            strict: false,
+            sct: None,
+            pass2: Some(Pass2Config {
+                codec: Codec::default(),
+                interactive_codec: None,
+                dictionary: Some(Dictionary::builtin()),
+            }),
+            codepoint: DEFAULT_CODEPOINT,
+            max_decompressed_size: None,
+            integrity_check: false,
+        }
+    }
+
+    /// Enables pass 1 with `dictionary`.
+    pub fn pass1_dictionary(mut self, dictionary: Arc<dyn CertDictionary>) -> Self {
+        self.pass1 = Some(dictionary);
+        self
+    }
+
+    /// Leaves certificates in place, so that only pass 2 runs.
+    pub fn without_pass1(mut self) -> Self {
+        self.pass1 = None;
+        self
+    }
+
+    /// Whether pass 1 also abridges stapled OCSP responses whose CA is in its dictionary. Has no
+    /// effect without pass 1.
+    pub fn ocsp_staples(mut self, enabled: bool) -> Self {
+        self.ocsp_staples = enabled;
+        self
+    }
+
+    /// Whether pass 1 insists on canonical encodings: the compressor refuses entries it could not
+    /// encode unambiguously and the decompressor rejects anything the compressor would have
+    /// encoded differently. Either side can be strict on its own, so this is not part of the
+    /// [`Profile`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Enables the SCT pass, which replaces the IDs of the CT logs in `dictionary` with short codes.
+    pub fn sct_dictionary(mut self, dictionary: Arc<LogDictionary>) -> Self {
+        self.sct = Some(dictionary);
+        self
+    }
+
+    fn pass2_mut(&mut self) -> &mut Pass2Config {
+        self.pass2.get_or_insert(Pass2Config {
+            codec: Codec::default(),
+            interactive_codec: None,
+            dictionary: Some(Dictionary::builtin()),
+        })
+    }
+
+    /// Enables pass 2 with `codec`, used for `CompressionLevel::Amortized`.
+    pub fn pass2_codec(mut self, codec: Codec) -> Self {
+        self.pass2_mut().codec = codec;
+        self
+    }
+
+    /// Enables pass 2 and sets the codec used for `CompressionLevel::Interactive`, which defaults
+    /// to [`Codec::interactive`] of the pass 2 codec.
+    pub fn interactive_codec(mut self, codec: Codec) -> Self {
+        self.pass2_mut().interactive_codec = Some(codec);
+        self
+    }
+
+    /// Enables pass 2 with a shared dictionary.
+    pub fn pass2_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.pass2_mut().dictionary = Some(dictionary);
+        self
+    }
+
+    /// Enables pass 2 without a shared dictionary.
+    pub fn without_pass2_dictionary(mut self) -> Self {
+        self.pass2_mut().dictionary = None;
+        self
+    }
+
+    /// Skips general purpose compression, so that only pass 1 runs.
+    pub fn without_pass2(mut self) -> Self {
+        self.pass2 = None;
+        self
+    }
+
+    /// Sets the codepoint advertised to rustls for this configuration.
+    pub fn codepoint(mut self, codepoint: u16) -> Self {
+        self.codepoint = codepoint;
+        self
+    }
+
+    /// Bounds the size of decompressed messages regardless of the length the peer declares.
+    pub fn max_decompressed_size(mut self, max_size: u32) -> Self {
+        self.max_decompressed_size = Some(max_size);
+        self
+    }
+
+    /// Prefixes the output with the pass 1 dictionary version and a checksum of the Certificate
+    /// message, which the decompressor checks before returning. Costs [`INTEGRITY_HEADER_LEN`]
+    /// bytes, but a dictionary mismatch between peers is reported as one rather than as an
+    /// unrelated certificate verification failure.
+    ///
+    /// [`INTEGRITY_HEADER_LEN`]: crate::INTEGRITY_HEADER_LEN
+    pub fn integrity_check(mut self, enabled: bool) -> Self {
+        self.integrity_check = enabled;
+        self
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.pass1.is_none() && self.sct.is_none() && self.pass2.is_none() {
+            return Err(Error::EmptyPipeline);
+        }
+        if !matches!(
+            CertificateCompressionAlgorithm::from(self.codepoint),
+            CertificateCompressionAlgorithm::Unknown(_)
+        ) {
+            return Err(Error::ReservedCodepoint(self.codepoint));
+        }
+        Ok(())
+    }
+
+    pub fn build_compressor(&self) -> Result<Compressor, Error> {
+        self.validate()?;
+        let p2 = match &self.pass2 {
+            Some(config) => {
+                let p2 = pass2::Compressor::new_with_codec(config.codec, config.dictionary.clone());
+                Some(match config.interactive_codec {
+                    Some(codec) => p2.with_interactive_codec(codec)?,
+                    None => p2,
+                })
+            }
+            None => None,
+        };
+        Ok(Compressor {
+            p1: self
+                .pass1
+                .clone()
+                .map(|dictionary| {
+                    pass1::Compressor::new(dictionary)
+                        .with_ocsp_staples(self.ocsp_staples)
+                        .with_strict(self.strict)
+                }),
+            sct: self.sct.clone().map(sct::Compressor::new),
+            p2,
+            codepoint: self.codepoint,
+            integrity_check: self.integrity_check,
+        })
+    }
+
+    pub fn build_decompressor(&self) -> Result<Decompressor, Error> {
+        self.validate()?;
+        Ok(Decompressor {
+            p1: self
+                .pass1
+                .clone()
+                .map(|dictionary| {
+                    pass1::Decompressor::new(dictionary)
+                        .with_ocsp_staples(self.ocsp_staples)
+                        .with_strict(self.strict)
+                }),
+            sct: self.sct.clone().map(sct::Decompressor::new),
+            p2: self
+                .pass2
+                .as_ref()
+                .map(|config| pass2::Decompressor::new_with_codec(config.codec, config.dictionary.clone())),
+            codepoint: self.codepoint,
+            max_size: self.max_decompressed_size,
+            integrity_check: self.integrity_check,
+        })
+    }
+
+    /// The profile shared by the compressor and decompressor this builder produces.
+    pub fn profile(&self) -> Profile {
+        Profile {
+            codepoint: self.codepoint,
+            pass1: self.pass1.as_ref().map(|dictionary| dictionary.metadata()),
+            ocsp_staples: self.pass1.is_some() && self.ocsp_staples,
+            sct: self.sct.as_ref().map(|dictionary| dictionary.id()),
+            pass2: self
+                .pass2
+                .as_ref()
+                .map(|config| Pass2Profile::new(config.codec, config.dictionary.as_ref())),
+            integrity_check: self.integrity_check,
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The pass 2 settings both peers must agree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass2Profile {
+    pub format: Format,
+    /// The identifier of the shared dictionary, if one is used.
+    pub dictionary: Option<[u8; DICTIONARY_ID_LEN]>,
+}
+
+impl Pass2Profile {
+    pub(crate) fn new(codec: Codec, dictionary: Option<&Dictionary>) -> Self {
+        Pass2Profile {
+            format: codec.format(),
+            dictionary: dictionary.map(Dictionary::id),
+        }
+    }
+}
+
+/// The parts of a configuration which determine the compressed format.
+///
+/// Compression parameters such as the Brotli quality and size limits are left out, since peers
+/// can choose them independently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub codepoint: u16,
+    /// The pass 1 dictionary, or `None` if pass 1 is disabled.
+    pub pass1: Option<DictionaryMetadata>,
+    /// Whether pass 1 abridges stapled OCSP responses.
+    pub ocsp_staples: bool,
+    /// The identifier of the CT log dictionary, or `None` if the SCT pass is disabled.
+    pub sct: Option<[u8; LOG_DICTIONARY_ID_LEN]>,
+    /// The pass 2 settings, or `None` if pass 2 is disabled.
+    pub pass2: Option<Pass2Profile>,
+    /// Whether the output starts with an integrity header.
+    pub integrity_check: bool,
+}
+
+impl Profile {
+    /// Checks that a peer configured with `other` reads and writes the same format, naming the
+    /// first setting that differs.
+    pub fn check(&self, other: &Profile) -> Result<(), Error> {
+        if self.codepoint != other.codepoint {
+            return Err(Error::ProfileMismatch("codepoint"));
+        }
+        if self.pass1 != other.pass1 {
+            return Err(Error::ProfileMismatch("pass 1 dictionary"));
+        }
+        if self.ocsp_staples != other.ocsp_staples {
+            return Err(Error::ProfileMismatch("OCSP staple compression"));
+        }
+        if self.integrity_check != other.integrity_check {
+            return Err(Error::ProfileMismatch("integrity check"));
+        }
+        if self.sct != other.sct {
+            return Err(Error::ProfileMismatch("SCT log dictionary"));
+        }
+        match (&self.pass2, &other.pass2) {
+            (Some(ours), Some(theirs)) if ours.format != theirs.format => {
+                Err(Error::ProfileMismatch("pass 2 codec"))
+            }
+            (Some(ours), Some(theirs)) if ours.dictionary != theirs.dictionary => {
+                Err(Error::ProfileMismatch("pass 2 dictionary"))
+            }
+            (Some(_), Some(_)) | (None, None) => Ok(()),
+            _ => Err(Error::ProfileMismatch("use of pass 2")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{certificate, certificate_message};
+    use crate::pass1::LoadedDictionary;
+
+    #[test]
+    fn pipelines_round_trip() {
+        let cert_bytes = certificate_message();
+        let builders = [
+            Builder::new(),
+            Builder::new().without_pass1(),
+            Builder::new().without_pass2(),
+            Builder::new().pass2_codec(Codec::zstd()),
+            Builder::new().without_pass2_dictionary(),
+            Builder::new().interactive_codec(Codec::Brotli { quality: 2, lgwin: 10 }),
+            Builder::new().sct_dictionary(Arc::new(LogDictionary::new(vec![]).unwrap())),
+            Builder::new().ocsp_staples(true),
+            Builder::new().ocsp_staples(true).strict(true),
+            Builder::new().integrity_check(true),
+            Builder::new().without_pass1().integrity_check(true),
+            Builder::new()
+                .without_pass1()
+                .without_pass2()
+                .sct_dictionary(Arc::new(LogDictionary::new(vec![]).unwrap())),
+        ];
+        for builder in builders {
+            let c = builder.build_compressor().expect("Valid configuration");
+            let d = builder.build_decompressor().expect("Valid configuration");
+            c.profile().check(&d.profile()).expect("Profiles match");
+            assert_eq!(c.profile(), builder.profile());
+            let out = c.compress_to_bytes(&cert_bytes).expect("Compression succeeds");
+            let round_trip = d.decompress_to_bytes(&out, 16000).expect("Decompression succeeds");
+            assert_eq!(cert_bytes, round_trip);
+        }
+    }
+
+    #[test]
+    fn single_pass_pipelines() {
+        let cert_bytes = certificate_message();
+        /* Without pass 2 the output is a Certificate message, without pass 1 it is opaque */
+        let c = Builder::new().without_pass2().build_compressor().unwrap();
+        let out = c.compress_to_bytes(&cert_bytes).unwrap();
+        assert_eq!(&out[..4], &cert_bytes[..4]);
+        let c = Builder::new().without_pass1().build_compressor().unwrap();
+        let out = c.compress_to_bytes(&cert_bytes).unwrap();
+        assert_eq!(
+            pass2::Decompressor::new_from_builtin().decompress_to_bytes(&out, 16000).unwrap(),
+            cert_bytes
+        );
+        assert!(matches!(
+            Builder::new().without_pass1().without_pass2().build_compressor(),
+            Err(Error::EmptyPipeline)
+        ));
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        assert!(matches!(
+            Builder::new().codepoint(2).build_decompressor(),
+            Err(Error::ReservedCodepoint(2))
+        ));
+        assert!(matches!(
+            Builder::new()
+                .pass2_codec(Codec::zstd())
+                .interactive_codec(Codec::brotli())
+                .build_compressor(),
+            Err(Error::IncompatibleCodecs)
+        ));
+    }
+
+    #[test]
+    fn size_limits() {
+        let cert_bytes = certificate_message();
+        for builder in [Builder::new(), Builder::new().without_pass2()] {
+            let out = builder
+                .build_compressor()
+                .unwrap()
+                .compress_to_bytes(&cert_bytes)
+                .unwrap();
+            let d = builder
+                .max_decompressed_size(cert_bytes.len() as u32 - 1)
+                .build_decompressor()
+                .unwrap();
+            assert!(matches!(
+                d.decompress_to_bytes(&out, 16000),
+                Err(Error::SizeLimitExceeded { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn integrity_checks() {
+        let cert_bytes = certificate_message();
+        let cert = certificate();
+        let mut other_cert = cert.clone();
+        *other_cert.last_mut().unwrap() ^= 1;
+        let dictionary = |version, cert: &[u8]| -> Arc<dyn CertDictionary> {
+            let entries = vec![(vec![0xff, 0x12, 0x34], cert.to_vec())];
+            let dictionary = LoadedDictionary::from_versioned_entries("2024-01-01".into(), version, entries, vec![])
+                .unwrap();
+            Arc::new(dictionary)
+        };
+        let ours = Builder::new().pass1_dictionary(dictionary(1, &cert));
+        let modified = Builder::new().pass1_dictionary(dictionary(1, &other_cert));
+        let newer = Builder::new().pass1_dictionary(dictionary(2, &cert));
+
+        /* Without the check, a dictionary which differs only in contents yields the wrong chain */
+        let out = ours.build_compressor().unwrap().compress_to_bytes(&cert_bytes).unwrap();
+        let wrong = modified.build_decompressor().unwrap().decompress_to_bytes(&out, 16000).unwrap();
+        assert_ne!(wrong, cert_bytes);
+
+        let c = ours.clone().integrity_check(true).build_compressor().unwrap();
+        let out = c.compress_to_bytes(&cert_bytes).unwrap();
+        assert_eq!(&out[..4], &[0, 0, 0, 1]);
+        let decompress = |builder: &Builder, input: &[u8]| {
+            let d = builder.clone().integrity_check(true).build_decompressor().unwrap();
+            d.decompress_to_bytes(input, 16000)
+        };
+        assert_eq!(decompress(&ours, &out).unwrap(), cert_bytes);
+        assert!(matches!(decompress(&modified, &out), Err(Error::ChecksumMismatch)));
+        assert!(matches!(
+            decompress(&newer, &out),
+            Err(Error::DictionaryVersionMismatch { ours: 2, theirs: 1 })
+        ));
+        assert!(matches!(decompress(&ours, &out[..4]), Err(Error::TruncatedStream)));
+    }
+
+    #[test]
+    fn mismatches_are_named() {
+        let base = Builder::new().profile();
+        let mismatch = |builder: Builder| match base.check(&builder.profile()) {
+            Err(Error::ProfileMismatch(setting)) => setting,
+            other => panic!("Expected a mismatch, got {:?}", other),
+        };
+        assert_eq!(mismatch(Builder::new().codepoint(0xfe01)), "codepoint");
+        assert_eq!(mismatch(Builder::new().without_pass1()), "pass 1 dictionary");
+        assert_eq!(mismatch(Builder::new().ocsp_staples(true)), "OCSP staple compression");
+        assert_eq!(mismatch(Builder::new().integrity_check(true)), "integrity check");
+        assert_eq!(
+            mismatch(Builder::new().sct_dictionary(Arc::new(LogDictionary::new(vec![]).unwrap()))),
+            "SCT log dictionary"
+        );
+        assert_eq!(mismatch(Builder::new().pass2_codec(Codec::zstd())), "pass 2 codec");
+        assert_eq!(
+            mismatch(Builder::new().without_pass2_dictionary()),
+            "pass 2 dictionary"
+        );
+        assert_eq!(mismatch(Builder::new().without_pass2()), "use of pass 2");
+        /* Parameters which only affect compression do not need to match */
+        let tuned = Builder::new()
+            .pass2_codec(Codec::Brotli { quality: 4, lgwin: 16 })
+            .max_decompressed_size(100)
+            .strict(true)
+            .profile();
+        base.check(&tuned).expect("Compatible");
+    }
+}
@@ -0,0 +1,238 @@
+//! Builds pass 1 dictionaries from CCADB `WebTrustListAsOf` CSV exports.
+//!
+//! The export for a given date can be downloaded from
+//! `https://ccadb.my.salesforce-sites.com/ccadb/WebTrustListAsOf?ListDate=YYYY-MM-DD`.
+
+use crate::pass1::{DictionaryDiff, IdentifierAllocator, LoadedDictionary, IDENTIFIER_PREFIX};
+use crate::{CcadbReason, Error};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io::Read;
+
+const DATE_ADDITION_COL: &str = "CCADB Record Creation Date";
+const FINGERPRINT_COL: &str = "SHA-256 Fingerprint";
+const CERT_PEM_COL: &str = "X.509 Certificate PEM";
+
+/// A certificate listed in a CCADB export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CcadbRecord {
+    /// When the record was added to the CCADB, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created: String,
+    pub der: Vec<u8>,
+}
+
+fn is_ccadb_timestamp(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 20
+        && bytes.iter().enumerate().all(|(i, c)| match i {
+            4 | 7 => *c == b'-',
+            10 => *c == b'T',
+            13 | 16 => *c == b':',
+            19 => *c == b'Z',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+pub fn sha256_fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+/// Reads the certificates out of a CCADB CSV export, checking each against its listed fingerprint.
+pub fn parse_webtrust_csv(reader: impl Read) -> Result<Vec<CcadbRecord>, Error> {
+    let header_error = |reason| Error::Ccadb { row: None, reason };
+    let mut csv = csv::Reader::from_reader(reader);
+    let headers = csv.headers().map_err(|e| header_error(CcadbReason::Csv(e)))?.clone();
+    let column = |name: &'static str| {
+        headers
+            .iter()
+            .position(|x| x == name)
+            .ok_or_else(|| header_error(CcadbReason::MissingColumn(name)))
+    };
+    let (date_col, fingerprint_col, pem_col) =
+        (column(DATE_ADDITION_COL)?, column(FINGERPRINT_COL)?, column(CERT_PEM_COL)?);
+
+    let mut records = Vec::new();
+    for (row, record) in csv.records().enumerate() {
+        let row_error = |reason| Error::Ccadb {
+            row: Some(row + 1),
+            reason,
+        };
+        let record = record.map_err(|e| row_error(CcadbReason::Csv(e)))?;
+        let created = &record[date_col];
+        if !is_ccadb_timestamp(created) {
+            return Err(row_error(CcadbReason::MalformedDate(created.to_string())));
+        }
+        let der = CertificateDer::from_pem_slice(record[pem_col].as_bytes())
+            .map_err(|_| row_error(CcadbReason::InvalidPem))?
+            .to_vec();
+        if !record[fingerprint_col].eq_ignore_ascii_case(&sha256_fingerprint(&der)) {
+            return Err(row_error(CcadbReason::FingerprintMismatch));
+        }
+        records.push(CcadbRecord {
+            created: created.to_string(),
+            der,
+        });
+    }
+    Ok(records)
+}
+
+/* The timestamp format sorts lexicographically, and the sort is stable for records added together */
+fn in_creation_order(mut records: Vec<CcadbRecord>) -> Vec<CcadbRecord> {
+    records.sort_by(|a, b| a.created.cmp(&b.created));
+    records
+}
+
+/// Orders certificates by the date they were added to the CCADB and allocates identifiers in
+/// that order, so that an export taken later only appends to the dictionary. Repeated
+/// certificates keep their first identifier.
+pub fn create_cert_dict(
+    records: Vec<CcadbRecord>,
+    list_date: &str,
+) -> Result<LoadedDictionary, Error> {
+    let records = in_creation_order(records);
+    let mut allocator = IdentifierAllocator::new(IDENTIFIER_PREFIX);
+    let mut seen = std::collections::HashSet::new();
+    let mut entries = Vec::with_capacity(records.len());
+    for record in records {
+        if !seen.insert(record.der.clone()) {
+            continue;
+        }
+        let id = allocator
+            .next_identifier()
+            .ok_or(Error::IdentifiersExhausted)?;
+        entries.push((id, record.der));
+    }
+    LoadedDictionary::from_entries(list_date.to_string(), entries)
+}
+
+/// Builds the next version of `previous` from a newer export. Existing identifiers are kept,
+/// new certificates are appended in creation order and missing ones are retired.
+pub fn update_cert_dict(
+    previous: &LoadedDictionary,
+    records: Vec<CcadbRecord>,
+    list_date: &str,
+) -> Result<(LoadedDictionary, DictionaryDiff), Error> {
+    let certs = in_creation_order(records).into_iter().map(|x| x.der);
+    previous.update(list_date.to_string(), certs)
+}
+
+/// Renders a Markdown table listing each identifier and the fingerprint of its certificate.
+pub fn markdown_listing(dictionary: &LoadedDictionary) -> String {
+    let mut output = String::new();
+    writeln!(output, "# Pass 1 dictionary\n").unwrap();
+    writeln!(
+        output,
+        "Version {}, built from the CCADB list as of {}.\n",
+        dictionary.version(),
+        dictionary.list_date()
+    )
+    .unwrap();
+    writeln!(output, "| Identifier | SHA-256 Fingerprint | Status |").unwrap();
+    writeln!(output, "|---|---|---|").unwrap();
+    for (id, cert) in dictionary.entries() {
+        let status = if dictionary.is_retired(id) { "retired" } else { "active" };
+        writeln!(output, "| `{}` | `{}` | {} |", hex::encode(id), sha256_fingerprint(cert), status).unwrap();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pass1::CertDictionary;
+
+    const FIXTURE: &[u8] = include_bytes!("../data/ccadb_webtrust_fixture.csv");
+
+    #[test]
+    fn parses_fixture() {
+        let records = parse_webtrust_csv(FIXTURE).expect("Fixture parses");
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|x| x.der[0] == 0x30));
+    }
+
+    #[test]
+    fn allocates_in_creation_order() {
+        let records = parse_webtrust_csv(FIXTURE).unwrap();
+        let dict = create_cert_dict(records.clone(), "2024-01-01").expect("Valid dictionary");
+        assert_eq!(dict.len(), 5);
+        /* Rows 2 and 4 share the oldest date and keep their relative order */
+        let expected = [1, 3, 2, 0, 4];
+        for (i, row) in expected.iter().enumerate() {
+            let id = [0xff, 0x00, i as u8];
+            assert_eq!(dict.identifier_to_cert(&id), Some(&records[*row].der[..]));
+        }
+    }
+
+    #[test]
+    fn duplicate_rows_keep_first_identifier() {
+        let mut records = parse_webtrust_csv(FIXTURE).unwrap();
+        records.push(records[0].clone());
+        let dict = create_cert_dict(records, "2024-01-01").unwrap();
+        assert_eq!(dict.len(), 5);
+    }
+
+    #[test]
+    fn rejects_bad_fingerprint() {
+        let mut csv = String::from_utf8(FIXTURE.to_vec()).unwrap();
+        let fingerprint = sha256_fingerprint(&parse_webtrust_csv(FIXTURE).unwrap()[0].der).to_uppercase();
+        csv = csv.replacen(&fingerprint, &"0".repeat(64), 1);
+        assert!(matches!(
+            parse_webtrust_csv(csv.as_bytes()),
+            Err(Error::Ccadb {
+                row: Some(1),
+                reason: CcadbReason::FingerprintMismatch
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_exports() {
+        let csv = String::from_utf8(FIXTURE.to_vec()).unwrap();
+        let renamed = csv.replacen(FINGERPRINT_COL, "Fingerprint", 1);
+        assert!(matches!(
+            parse_webtrust_csv(renamed.as_bytes()),
+            Err(Error::Ccadb {
+                row: None,
+                reason: CcadbReason::MissingColumn(FINGERPRINT_COL)
+            })
+        ));
+        let no_pem = csv.replacen("-----BEGIN CERTIFICATE-----", "", 1);
+        assert!(matches!(
+            parse_webtrust_csv(no_pem.as_bytes()),
+            Err(Error::Ccadb {
+                row: Some(_),
+                reason: CcadbReason::InvalidPem
+            })
+        ));
+    }
+
+    #[test]
+    fn allocator_exhausts() {
+        let mut allocator = IdentifierAllocator::starting_at(0xff, u16::MAX.into());
+        assert_eq!(allocator.next_identifier(), Some(vec![0xff, 0xff, 0xff]));
+        assert_eq!(allocator.next_identifier(), None);
+    }
+
+    #[test]
+    fn updates_keep_identifiers() {
+        let records = parse_webtrust_csv(FIXTURE).unwrap();
+        let first = create_cert_dict(records[..3].to_vec(), "2024-01-01").unwrap();
+        let (second, diff) = update_cert_dict(&first, records[1..].to_vec(), "2024-02-01").unwrap();
+        for (id, cert) in first.entries() {
+            assert_eq!(second.identifier_to_cert(id), Some(cert));
+        }
+        assert_eq!(second.version(), 2);
+        assert_eq!(diff.added, ["ff0003", "ff0004"]);
+        assert_eq!(second.cert_to_identifier(&records[0].der), None);
+        assert_eq!(diff.retired.len(), 1);
+    }
+
+    #[test]
+    fn listing_has_every_entry() {
+        let dict = create_cert_dict(parse_webtrust_csv(FIXTURE).unwrap(), "2024-01-01").unwrap();
+        let listing = markdown_listing(&dict);
+        assert_eq!(listing.lines().filter(|x| x.starts_with("| `ff")).count(), 5);
+    }
+}
@@ -0,0 +1,249 @@
+//! Certificate chains outside TLS, e.g. read from a PEM bundle or a PKCS#7 file and kept in a
+//! database or log.
+//!
+//! [`Compressor::compress_chain`](crate::Compressor::compress_chain) wraps a chain in a
+//! Certificate message with an empty request context and no entry extensions, then runs the usual
+//! passes over it, so stored chains share the dictionaries and format used in TLS.
+//! [`Decompressor::decompress_chain`](crate::Decompressor::decompress_chain) only accepts messages
+//! of that shape.
+
+use base64::prelude::*;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+
+use crate::der::{children, read_element};
+use crate::tls::{CertificateEntry, CertificateMessage};
+use crate::Error;
+
+/* 1.2.840.113549.1.7.2, the PKCS#7 signedData content type */
+const SIGNED_DATA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const PKCS7_PEM_BEGIN: &str = "-----BEGIN PKCS7-----";
+const PKCS7_PEM_END: &str = "-----END PKCS7-----";
+
+pub(crate) fn to_message(chain: &[CertificateDer]) -> Result<Vec<u8>, Error> {
+    CertificateMessage {
+        request_context: &[],
+        certificate_entries: chain
+            .iter()
+            .map(|cert| CertificateEntry {
+                data: cert.as_ref(),
+                extensions: &[],
+            })
+            .collect(),
+    }
+    .to_vec()
+}
+
+pub(crate) fn from_message(cert_msg: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let msg = CertificateMessage::read_from_bytes(cert_msg)?;
+    if !msg.request_context.is_empty() {
+        return Err(Error::InvalidChain("message has a request context".into()));
+    }
+    msg.certificate_entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| match entry.extensions {
+            [] => Ok(CertificateDer::from(entry.data.to_vec())),
+            _ => Err(Error::InvalidChain(format!("certificate entry {} has extensions", i))),
+        })
+        .collect()
+}
+
+/// Reads every certificate in a PEM bundle, in order. Other sections are ignored.
+pub fn from_pem_bundle(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let chain = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::InvalidChain(format!("invalid PEM: {:?}", e)))?;
+    if chain.is_empty() {
+        return Err(Error::InvalidChain("no certificates in PEM bundle".into()));
+    }
+    Ok(chain)
+}
+
+/// Reads the certificates of a PKCS#7 signedData structure, as found in `.p7b` files, in the
+/// order they are stored. Both DER and PEM are accepted.
+pub fn from_pkcs7(input: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let malformed = || Error::InvalidChain("malformed PKCS#7".into());
+    let armored = std::str::from_utf8(input)
+        .ok()
+        .and_then(|text| Some(text.split_once(PKCS7_PEM_BEGIN)?.1.split_once(PKCS7_PEM_END)?.0));
+    let decoded;
+    let der = match armored {
+        Some(base64) => {
+            let base64: String = base64.chars().filter(|c| !c.is_whitespace()).collect();
+            decoded = BASE64_STANDARD.decode(base64).map_err(|_| malformed())?;
+            &decoded[..]
+        }
+        None => input,
+    };
+
+    /* ContentInfo ::= SEQUENCE { contentType, [0] EXPLICIT SignedData } */
+    let content_info = read_element(der, 0, der.len())
+        .filter(|x| x.tag == 0x30 && x.contents.end == der.len())
+        .ok_or_else(malformed)?;
+    let mut fields = children(der, &content_info);
+    match fields.next() {
+        Some(oid) if oid.tag == 0x06 && &der[oid.contents.clone()] == SIGNED_DATA_OID => {}
+        _ => return Err(Error::InvalidChain("not PKCS#7 signedData".into())),
+    }
+    let signed_data = fields
+        .next()
+        .filter(|x| x.tag == 0xa0)
+        .and_then(|content| children(der, &content).next())
+        .filter(|x| x.tag == 0x30)
+        .ok_or_else(malformed)?;
+
+    /* The certificates are the optional [0] IMPLICIT SET OF field of SignedData */
+    let Some(certificates) = children(der, &signed_data).find(|x| x.tag == 0xa0) else {
+        return Err(Error::InvalidChain("no certificates in PKCS#7".into()));
+    };
+    let mut chain = Vec::new();
+    let mut end = certificates.contents.start;
+    for cert in children(der, &certificates) {
+        if cert.tag != 0x30 {
+            return Err(Error::InvalidChain("unsupported PKCS#7 certificate type".into()));
+        }
+        end = cert.contents.end;
+        chain.push(CertificateDer::from(der[cert.range()].to_vec()));
+    }
+    if end != certificates.contents.end {
+        return Err(malformed());
+    }
+    if chain.is_empty() {
+        return Err(Error::InvalidChain("no certificates in PKCS#7".into()));
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{certificate, certificate_message};
+    use crate::der::write_der_len;
+    use crate::pass2::Codec;
+    use crate::Builder;
+
+    fn sample_chain() -> Vec<CertificateDer<'static>> {
+        vec![CertificateDer::from(certificate())]
+    }
+
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut output = vec![tag];
+        write_der_len(contents.len(), &mut output);
+        output.extend_from_slice(contents);
+        output
+    }
+
+    fn pkcs7(chain: &[CertificateDer]) -> Vec<u8> {
+        let certificates: Vec<u8> = chain.iter().flat_map(|cert| cert.to_vec()).collect();
+        let signed_data = [
+            tlv(0x02, &[1]),
+            tlv(0x31, &[]),
+            tlv(0x30, &tlv(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01])),
+            tlv(0xa0, &certificates),
+            tlv(0x31, &[]),
+        ]
+        .concat();
+        let content = [tlv(0x06, SIGNED_DATA_OID), tlv(0xa0, &tlv(0x30, &signed_data))].concat();
+        tlv(0x30, &content)
+    }
+
+    #[test]
+    fn chain_round_trip() {
+        let chain = sample_chain();
+        let builders = [
+            Builder::new(),
+            Builder::new().pass2_codec(Codec::zstd()),
+            Builder::new().without_pass2(),
+        ];
+        for builder in builders {
+            let c = builder.build_compressor().unwrap();
+            let d = builder.build_decompressor().unwrap();
+            let compressed = c.compress_chain(&chain).expect("Compression succeeds");
+            /* The chain is compressed as the equivalent Certificate message would be */
+            assert_eq!(compressed, c.compress_to_bytes(&to_message(&chain).unwrap()).unwrap());
+            assert_eq!(d.decompress_chain(&compressed, 16000).expect("Decompression succeeds"), chain);
+        }
+        let c = Builder::new().build_compressor().unwrap();
+        let d = Builder::new().build_decompressor().unwrap();
+        assert_eq!(d.decompress_chain(&c.compress_chain(&[]).unwrap(), 16000).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn only_bare_chains() {
+        let c = Builder::new().build_compressor().unwrap();
+        let d = Builder::new().build_decompressor().unwrap();
+        /* The sample message has an empty request context and no extensions, so it is a bare chain */
+        let cert_bytes = certificate_message();
+        let compressed = c.compress_to_bytes(&cert_bytes).unwrap();
+        assert_eq!(d.decompress_chain(&compressed, 16000).unwrap(), sample_chain());
+
+        let mut with_context = cert_bytes.clone();
+        with_context[0] = 1;
+        with_context.insert(1, 0xaa);
+        let compressed = c.compress_to_bytes(&with_context).unwrap();
+        assert!(matches!(d.decompress_chain(&compressed, 16000), Err(Error::InvalidChain(_))));
+
+        let msg = CertificateMessage::read_from_bytes(&cert_bytes).unwrap();
+        let with_extensions = CertificateMessage {
+            request_context: &[],
+            certificate_entries: vec![CertificateEntry {
+                data: msg.certificate_entries[0].data,
+                extensions: &[0, 18, 0, 0],
+            }],
+        }
+        .to_vec()
+        .unwrap();
+        let compressed = c.compress_to_bytes(&with_extensions).unwrap();
+        assert!(matches!(d.decompress_chain(&compressed, 16000), Err(Error::InvalidChain(_))));
+    }
+
+    #[test]
+    fn pem_bundles() {
+        let leaf = rcgen::generate_simple_self_signed(vec!["leaf.example".into()]).unwrap();
+        let ca = rcgen::generate_simple_self_signed(vec!["ca.example".into()]).unwrap();
+        let bundle = format!(
+            "{}\n{}{}",
+            leaf.cert.pem(),
+            leaf.signing_key.serialize_pem(),
+            ca.cert.pem()
+        );
+        let chain = from_pem_bundle(bundle.as_bytes()).expect("Valid bundle");
+        assert_eq!(chain, vec![leaf.cert.der().clone(), ca.cert.der().clone()]);
+
+        assert!(matches!(from_pem_bundle(b"not a bundle"), Err(Error::InvalidChain(_))));
+        let truncated = &leaf.cert.pem()[..100];
+        assert!(matches!(from_pem_bundle(truncated.as_bytes()), Err(Error::InvalidChain(_))));
+    }
+
+    #[test]
+    fn pkcs7_files() {
+        let leaf = rcgen::generate_simple_self_signed(vec!["leaf.example".into()]).unwrap();
+        let chain = [sample_chain(), vec![leaf.cert.der().clone()]].concat();
+        let der = pkcs7(&chain);
+        assert_eq!(from_pkcs7(&der).expect("Valid DER"), chain);
+        let pem = format!(
+            "{}\n{}\n{}\n",
+            PKCS7_PEM_BEGIN,
+            BASE64_STANDARD
+                .encode(&der)
+                .as_bytes()
+                .chunks(64)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PKCS7_PEM_END
+        );
+        assert_eq!(from_pkcs7(pem.as_bytes()).expect("Valid PEM"), chain);
+
+        let invalid = |input: &[u8]| matches!(from_pkcs7(input), Err(Error::InvalidChain(_)));
+        assert!(invalid(&pkcs7(&[])));
+        assert!(invalid(&der[..der.len() - 1]));
+        let mut wrong_type = der.clone();
+        let oid = wrong_type.windows(SIGNED_DATA_OID.len()).position(|x| x == SIGNED_DATA_OID).unwrap();
+        wrong_type[oid + SIGNED_DATA_OID.len() - 1] = 1;
+        assert!(invalid(&wrong_type));
+        let without_certificates = [tlv(0x06, SIGNED_DATA_OID), tlv(0xa0, &tlv(0x30, &tlv(0x02, &[1])))].concat();
+        assert!(invalid(&tlv(0x30, &without_certificates)));
+    }
+}
@@ -0,0 +1,59 @@
+//! Just enough DER to find and rewrite fields inside certificates and OCSP responses without
+//! a full ASN.1 parser. Lengths of up to 4 bytes are accepted; anything else is treated as
+//! malformed and left to the caller to skip.
+
+use std::ops::Range;
+
+/* A DER element: its tag, where it starts and the range of its contents */
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+    pub tag: u8,
+    pub start: usize,
+    pub contents: Range<usize>,
+}
+
+impl Element {
+    /// The whole element, including its tag and length.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.contents.end
+    }
+}
+
+/* Reads the element at `start`, which must end by `end` */
+pub(crate) fn read_element(der: &[u8], start: usize, end: usize) -> Option<Element> {
+    let tag = *der.get(start)?;
+    let first = *der.get(start + 1)? as usize;
+    let (len, header) = match first {
+        0..=0x7f => (first, 2),
+        0x81..=0x84 => {
+            let width = first - 0x80;
+            let bytes = der.get(start + 2..start + 2 + width)?;
+            (bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize), 2 + width)
+        }
+        _ => return None,
+    };
+    let contents = start + header..(start + header).checked_add(len)?;
+    (contents.end <= end).then_some(Element { tag, start, contents })
+}
+
+pub(crate) fn children<'a>(der: &'a [u8], parent: &Element) -> impl Iterator<Item = Element> + 'a {
+    let end = parent.contents.end;
+    let mut next = Some(parent.contents.start);
+    std::iter::from_fn(move || {
+        let start = next.filter(|x| *x < end)?;
+        let element = read_element(der, start, end);
+        next = element.as_ref().map(|x| x.contents.end);
+        element
+    })
+}
+
+pub(crate) fn write_der_len(len: usize, output: &mut Vec<u8>) {
+    if len < 0x80 {
+        output.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        output.push(0x80 | (4 - skip) as u8);
+        output.extend_from_slice(&bytes[skip..]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+/// Everything that can go wrong while compressing or decompressing a Certificate message.
+///
+/// Offsets are in bytes from the start of the message being parsed. Entry indexes count
+/// certificate entries from zero and are `None` for fields outside the certificate list.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The message ended in the middle of a length field.
+    TruncatedLengthField { offset: usize, entry: Option<usize> },
+    /// A length field claimed more bytes than remain in the message.
+    LengthExceedsInput { offset: usize, entry: Option<usize> },
+    /// Bytes followed the end of the certificate list.
+    TrailingData { offset: usize },
+    /// A value was too large for the length field it is written with.
+    LengthOverflow,
+    /// The data of a certificate extension of the given type did not match its definition.
+    MalformedExtension(u16),
+    /// An abridged OCSP response, or for a strict decompressor a certificate entry, held an
+    /// identifier the pass 1 dictionary does not contain.
+    UnknownIdentifier {
+        identifier: Vec<u8>,
+        offset: usize,
+        entry: usize,
+    },
+    /// A strict compressor was given a certificate entry that would decode as an identifier.
+    AmbiguousLiteral { entry: usize },
+    /// A strict decompressor was given a field which the compressor would have encoded
+    /// differently, such as a dictionary certificate sent in full.
+    NonCanonical { offset: usize, entry: usize },
+    /// The integrity header names a different pass 1 dictionary version from ours, so the peers
+    /// would disagree on what identifiers stand for.
+    DictionaryVersionMismatch { ours: u32, theirs: u32 },
+    /// The decompressed message does not match the checksum in the integrity header.
+    ChecksumMismatch,
+    /// A framed message's header was malformed or did not describe its contents.
+    InvalidFrame(&'static str),
+    /// A certificate chain failed to parse, or a decompressed message was not a bare chain.
+    InvalidChain(String),
+    /// The message would decompress to more than the caller's limit.
+    SizeLimitExceeded { limit: usize },
+    /// The message was compressed with a different pass 2 dictionary, or with one when we have
+    /// none, or without one when we have one.
+    DictionaryMismatch,
+    /// The message was compressed with a different set of CT logs, or without the SCT pass.
+    LogDictionaryMismatch,
+    /// An abridged SCT held a code the log dictionary does not contain.
+    UnknownLogCode(u16),
+    /// An SCT used the version reserved for abridged SCTs, so it could not be compressed losslessly.
+    ReservedSctVersion,
+    /// A stapled OCSP response used the status type reserved for abridged responses.
+    ReservedStatusType,
+    /// The compressed data ended before the codec's end of stream marker.
+    TruncatedStream,
+    /// The pass 2 codec rejected its input.
+    Codec(std::io::Error),
+    /// Two compression levels were configured with codecs that produce different formats.
+    IncompatibleCodecs,
+    /// A pipeline was configured with no passes enabled.
+    EmptyPipeline,
+    /// Two pipelines disagree on the named setting, so one cannot decode the other's output.
+    ProfileMismatch(&'static str),
+    /// A pass 1 dictionary or CT log list failed to parse or validate.
+    InvalidDictionary(String),
+    /// A CCADB export was rejected. `row` counts records from 1 and is `None` for the header.
+    Ccadb { row: Option<usize>, reason: CcadbReason },
+    /// Every identifier under the prefix has been allocated.
+    IdentifiersExhausted,
+    /// A codepoint is assigned by IANA to another compression algorithm.
+    ReservedCodepoint(u16),
+    /// The same codepoint was configured for two dictionary versions.
+    DuplicateCodepoint(u16),
+    /// Writing the output failed.
+    Io(std::io::Error),
+}
+
+/// Why a CCADB export was rejected.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CcadbReason {
+    /// The export has no column with this name.
+    MissingColumn(&'static str),
+    /// The export is not valid CSV.
+    Csv(csv::Error),
+    /// A creation date is not a `YYYY-MM-DDTHH:MM:SSZ` timestamp.
+    MalformedDate(String),
+    /// A certificate is not valid PEM.
+    InvalidPem,
+    /// A certificate does not match its listed SHA-256 fingerprint.
+    FingerprintMismatch,
+}
+
+impl fmt::Display for CcadbReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CcadbReason::MissingColumn(name) => write!(f, "missing column {}", name),
+            CcadbReason::Csv(e) => write!(f, "{}", e),
+            CcadbReason::MalformedDate(date) => write!(f, "malformed creation date {:?}", date),
+            CcadbReason::InvalidPem => write!(f, "invalid PEM"),
+            CcadbReason::FingerprintMismatch => {
+                write!(f, "certificate does not match its SHA-256 fingerprint")
+            }
+        }
+    }
+}
+
+fn fmt_entry(f: &mut fmt::Formatter<'_>, entry: &Option<usize>) -> fmt::Result {
+    match entry {
+        Some(entry) => write!(f, " in certificate entry {}", entry),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TruncatedLengthField { offset, entry } => {
+                write!(f, "Not enough bytes to read length field at offset {}", offset)?;
+                fmt_entry(f, entry)
+            }
+            Error::LengthExceedsInput { offset, entry } => {
+                write!(f, "Length field at offset {} longer than remaining bytes", offset)?;
+                fmt_entry(f, entry)
+            }
+            Error::TrailingData { offset } => {
+                write!(f, "Trailing data inside Certificate Message at offset {}", offset)
+            }
+            Error::LengthOverflow => write!(f, "Value too large for Integer"),
+            Error::MalformedExtension(extension_type) => {
+                write!(f, "Malformed data in certificate extension {}", extension_type)
+            }
+            Error::UnknownIdentifier {
+                identifier,
+                offset,
+                entry,
+            } => write!(
+                f,
+                "Unknown identifier {} at offset {} in certificate entry {}",
+                hex::encode(identifier),
+                offset,
+                entry
+            ),
+            Error::AmbiguousLiteral { entry } => {
+                write!(f, "Certificate entry {} would be mistaken for an identifier", entry)
+            }
+            Error::NonCanonical { offset, entry } => write!(
+                f,
+                "Non-canonical encoding at offset {} in certificate entry {}",
+                offset, entry
+            ),
+            Error::DictionaryVersionMismatch { ours, theirs } => write!(
+                f,
+                "Peer compressed with pass 1 dictionary version {} but we have version {}",
+                theirs, ours
+            ),
+            Error::ChecksumMismatch => write!(
+                f,
+                "Decompressed chain does not match the compressed one, likely because the peers' \
+                 dictionaries have the same version but different contents"
+            ),
+            Error::InvalidFrame(reason) => write!(f, "Invalid frame: {}", reason),
+            Error::InvalidChain(reason) => write!(f, "Invalid certificate chain: {}", reason),
+            Error::SizeLimitExceeded { limit } => {
+                write!(f, "Decompressed message exceeds the limit of {} bytes", limit)
+            }
+            Error::DictionaryMismatch => {
+                write!(f, "Message was compressed with a different pass 2 dictionary")
+            }
+            Error::LogDictionaryMismatch => {
+                write!(f, "Message was compressed with a different CT log dictionary")
+            }
+            Error::UnknownLogCode(code) => write!(f, "Unknown CT log code {}", code),
+            Error::ReservedSctVersion => write!(f, "SCT uses the version reserved for abridged SCTs"),
+            Error::ReservedStatusType => {
+                write!(f, "OCSP staple uses the status type reserved for abridged responses")
+            }
+            Error::TruncatedStream => write!(f, "Compressed data ended unexpectedly"),
+            Error::Codec(e) => write!(f, "Pass 2 codec error: {}", e),
+            Error::IncompatibleCodecs => {
+                write!(f, "Every compression level must use the same pass 2 codec")
+            }
+            Error::EmptyPipeline => write!(f, "At least one pass must be enabled"),
+            Error::ProfileMismatch(setting) => write!(f, "Peers disagree on the {}", setting),
+            Error::InvalidDictionary(reason) => write!(f, "Invalid dictionary: {}", reason),
+            Error::Ccadb { row: None, reason } => write!(f, "Invalid CCADB export: {}", reason),
+            Error::Ccadb { row: Some(row), reason } => {
+                write!(f, "Invalid CCADB export at row {}: {}", row, reason)
+            }
+            Error::IdentifiersExhausted => write!(f, "Ran out of identifiers"),
+            Error::ReservedCodepoint(codepoint) => {
+                write!(f, "Codepoint {} is already assigned by IANA", codepoint)
+            }
+            Error::DuplicateCodepoint(codepoint) => {
+                write!(f, "Codepoint {} is used more than once", codepoint)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Codec(e) | Error::Io(e) => Some(e),
+            Error::Ccadb {
+                reason: CcadbReason::Csv(e),
+                ..
+            } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
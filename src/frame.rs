@@ -0,0 +1,300 @@
+//! A self-describing container for compressed messages stored outside TLS.
+//!
+//! In TLS the codepoint tells the peer how a message was compressed. A stored blob has no
+//! codepoint, so [`Compressor::compress_framed`](crate::Compressor::compress_framed) prefixes it
+//! with a header recording the settings which produced it:
+//!
+//! ```text
+//! magic "ACF" | format version | flags
+//! | pass 1 dictionary version[4]      if pass 1 is enabled
+//! | SCT log dictionary id[4]          if the SCT pass is enabled
+//! | codec | codec parameters          if pass 2 is enabled
+//! | pass 2 dictionary id[4]           if pass 2 uses a dictionary
+//! | uncompressed length[4]
+//! ```
+//!
+//! Integers are big endian. Brotli's parameters are its quality and window size, one byte each,
+//! and zstd's is its level as 4 bytes.
+
+use crate::pass2::{Codec, Format, DICTIONARY_ID_LEN};
+use crate::sct::LOG_DICTIONARY_ID_LEN;
+use crate::{Error, Profile};
+
+pub const FRAME_MAGIC: &[u8; 3] = b"ACF";
+pub const FRAME_FORMAT_VERSION: u8 = 1;
+
+const PASS1: u8 = 0x01;
+const OCSP_STAPLES: u8 = 0x02;
+const SCT: u8 = 0x04;
+const PASS2: u8 = 0x08;
+const PASS2_DICTIONARY: u8 = 0x10;
+const INTEGRITY_CHECK: u8 = 0x20;
+const KNOWN_FLAGS: u8 = PASS1 | OCSP_STAPLES | SCT | PASS2 | PASS2_DICTIONARY | INTEGRITY_CHECK;
+
+const BROTLI: u8 = 0;
+const ZSTD: u8 = 1;
+
+/// Describes how a framed message was compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The version of the pass 1 dictionary, or `None` if pass 1 was disabled.
+    pub pass1_version: Option<u32>,
+    pub ocsp_staples: bool,
+    /// The identifier of the CT log dictionary, or `None` if the SCT pass was disabled.
+    pub sct: Option<[u8; LOG_DICTIONARY_ID_LEN]>,
+    /// The pass 2 codec and the parameters it compressed with, or `None` if pass 2 was disabled.
+    pub codec: Option<Codec>,
+    /// The identifier of the pass 2 dictionary, if one was used.
+    pub pass2_dictionary: Option<[u8; DICTIONARY_ID_LEN]>,
+    pub integrity_check: bool,
+    /// The length of the Certificate message.
+    pub uncompressed_len: u32,
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        return Err(Error::InvalidFrame("header is truncated"));
+    }
+    let (value, rest) = input.split_at(len);
+    *input = rest;
+    Ok(value)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], Error> {
+    let mut value = [0; N];
+    value.copy_from_slice(take(input, N)?);
+    Ok(value)
+}
+
+impl FrameHeader {
+    /// Describes output in the format `profile` describes, compressed with `codec`.
+    pub fn new(profile: &Profile, codec: Option<Codec>, uncompressed_len: u32) -> Self {
+        FrameHeader {
+            pass1_version: profile.pass1.as_ref().map(|metadata| metadata.version),
+            ocsp_staples: profile.ocsp_staples,
+            sct: profile.sct,
+            codec,
+            pass2_dictionary: profile.pass2.and_then(|pass2| pass2.dictionary),
+            integrity_check: profile.integrity_check,
+            uncompressed_len,
+        }
+    }
+
+    /// Splits a framed message into its header and the compressed message.
+    pub fn read(mut input: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if take(&mut input, FRAME_MAGIC.len())? != FRAME_MAGIC {
+            return Err(Error::InvalidFrame("missing magic"));
+        }
+        if take(&mut input, 1)?[0] != FRAME_FORMAT_VERSION {
+            return Err(Error::InvalidFrame("unsupported format version"));
+        }
+        let flags = take(&mut input, 1)?[0];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::InvalidFrame("unknown flags"));
+        }
+        if flags & PASS2 == 0 && flags & PASS2_DICTIONARY != 0 {
+            return Err(Error::InvalidFrame("pass 2 dictionary without pass 2"));
+        }
+        let pass1_version = match flags & PASS1 {
+            0 => None,
+            _ => Some(u32::from_be_bytes(take_array(&mut input)?)),
+        };
+        let sct = match flags & SCT {
+            0 => None,
+            _ => Some(take_array(&mut input)?),
+        };
+        let codec = match flags & PASS2 {
+            0 => None,
+            _ => Some(match take(&mut input, 1)?[0] {
+                BROTLI => {
+                    let [quality, lgwin] = take_array(&mut input)?;
+                    Codec::Brotli {
+                        quality: quality as u32,
+                        lgwin: lgwin as u32,
+                    }
+                }
+                ZSTD => Codec::Zstd {
+                    level: i32::from_be_bytes(take_array(&mut input)?),
+                },
+                _ => return Err(Error::InvalidFrame("unknown codec")),
+            }),
+        };
+        let pass2_dictionary = match flags & PASS2_DICTIONARY {
+            0 => None,
+            _ => Some(take_array(&mut input)?),
+        };
+        let uncompressed_len = u32::from_be_bytes(take_array(&mut input)?);
+        let header = FrameHeader {
+            pass1_version,
+            ocsp_staples: flags & OCSP_STAPLES != 0,
+            sct,
+            codec,
+            pass2_dictionary,
+            integrity_check: flags & INTEGRITY_CHECK != 0,
+            uncompressed_len,
+        };
+        Ok((header, input))
+    }
+
+    pub fn write_to_bytes(&self, output: &mut Vec<u8>) -> Result<(), Error> {
+        let mut flags = 0;
+        let set = |flag: u8, enabled: bool| if enabled { flag } else { 0 };
+        flags |= set(PASS1, self.pass1_version.is_some());
+        flags |= set(OCSP_STAPLES, self.ocsp_staples);
+        flags |= set(SCT, self.sct.is_some());
+        flags |= set(PASS2, self.codec.is_some());
+        flags |= set(PASS2_DICTIONARY, self.pass2_dictionary.is_some());
+        flags |= set(INTEGRITY_CHECK, self.integrity_check);
+        if self.codec.is_none() && self.pass2_dictionary.is_some() {
+            return Err(Error::InvalidFrame("pass 2 dictionary without pass 2"));
+        }
+        output.extend_from_slice(FRAME_MAGIC);
+        output.push(FRAME_FORMAT_VERSION);
+        output.push(flags);
+        if let Some(version) = self.pass1_version {
+            output.extend_from_slice(&version.to_be_bytes());
+        }
+        if let Some(id) = self.sct {
+            output.extend_from_slice(&id);
+        }
+        match self.codec {
+            Some(Codec::Brotli { quality, lgwin }) => {
+                let quality = u8::try_from(quality).map_err(|_| Error::LengthOverflow)?;
+                let lgwin = u8::try_from(lgwin).map_err(|_| Error::LengthOverflow)?;
+                output.extend_from_slice(&[BROTLI, quality, lgwin]);
+            }
+            Some(Codec::Zstd { level }) => {
+                output.push(ZSTD);
+                output.extend_from_slice(&level.to_be_bytes());
+            }
+            None => {}
+        }
+        if let Some(id) = self.pass2_dictionary {
+            output.extend_from_slice(&id);
+        }
+        output.extend_from_slice(&self.uncompressed_len.to_be_bytes());
+        Ok(())
+    }
+
+    /// Checks that a `Decompressor` with `profile` can read the message, naming the first setting
+    /// that differs as [`Profile::check`] does. The codec parameters and the codepoint don't matter.
+    pub fn check(&self, profile: &Profile) -> Result<(), Error> {
+        if self.pass1_version != profile.pass1.as_ref().map(|metadata| metadata.version) {
+            return Err(Error::ProfileMismatch("pass 1 dictionary"));
+        }
+        if self.ocsp_staples != profile.ocsp_staples {
+            return Err(Error::ProfileMismatch("OCSP staple compression"));
+        }
+        if self.integrity_check != profile.integrity_check {
+            return Err(Error::ProfileMismatch("integrity check"));
+        }
+        if self.sct != profile.sct {
+            return Err(Error::ProfileMismatch("SCT log dictionary"));
+        }
+        match (self.codec.as_ref().map(Codec::format), &profile.pass2) {
+            (Some(ours), Some(theirs)) if ours != theirs.format => Err(Error::ProfileMismatch("pass 2 codec")),
+            (Some(_), Some(theirs)) if self.pass2_dictionary != theirs.dictionary => {
+                Err(Error::ProfileMismatch("pass 2 dictionary"))
+            }
+            (Some(_), Some(_)) | (None, None) => Ok(()),
+            _ => Err(Error::ProfileMismatch("use of pass 2")),
+        }
+    }
+
+    /// The pass 2 wire format, if pass 2 was used.
+    pub fn format(&self) -> Option<Format> {
+        self.codec.as_ref().map(Codec::format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::certificate_message;
+    use crate::pass2::Dictionary;
+    use crate::Builder;
+
+    #[test]
+    fn framed_round_trip() {
+        let cert_bytes = certificate_message();
+        let builders = [
+            Builder::new(),
+            Builder::new().without_pass1(),
+            Builder::new().without_pass2().integrity_check(true),
+            Builder::new()
+                .pass2_codec(Codec::zstd())
+                .pass2_dictionary(Dictionary::builtin())
+                .ocsp_staples(true),
+        ];
+        for builder in builders {
+            let c = builder.build_compressor().unwrap();
+            let framed = c.compress_framed(&cert_bytes).expect("Compression succeeds");
+            let (header, body) = FrameHeader::read(&framed).expect("Valid header");
+            assert_eq!(header.uncompressed_len as usize, cert_bytes.len());
+            assert_eq!(header.format(), c.profile().pass2.map(|pass2| pass2.format));
+            assert_eq!(body, c.compress_to_bytes(&cert_bytes).unwrap());
+            let mut written = Vec::new();
+            header.write_to_bytes(&mut written).unwrap();
+            assert_eq!(written, &framed[..framed.len() - body.len()]);
+
+            let d = builder.build_decompressor().unwrap();
+            assert_eq!(d.decompress_framed(&framed, 16000).expect("Decompression succeeds"), cert_bytes);
+        }
+    }
+
+    #[test]
+    fn mismatched_frames() {
+        let cert_bytes = certificate_message();
+        let framed = Builder::new()
+            .pass2_codec(Codec::zstd())
+            .build_compressor()
+            .unwrap()
+            .compress_framed(&cert_bytes)
+            .unwrap();
+        let mismatch = |builder: Builder| match builder.build_decompressor().unwrap().decompress_framed(&framed, 16000) {
+            Err(Error::ProfileMismatch(setting)) => setting,
+            other => panic!("Expected a mismatch, got {:?}", other),
+        };
+        assert_eq!(mismatch(Builder::new()), "pass 2 codec");
+        assert_eq!(mismatch(Builder::new().pass2_codec(Codec::zstd()).without_pass1()), "pass 1 dictionary");
+        assert_eq!(mismatch(Builder::new().without_pass2()), "use of pass 2");
+        /* Parameters only affect compression */
+        let d = Builder::new().pass2_codec(Codec::Zstd { level: 1 }).build_decompressor().unwrap();
+        assert_eq!(d.decompress_framed(&framed, 16000).unwrap(), cert_bytes);
+        assert!(matches!(
+            d.decompress_framed(&framed, cert_bytes.len() as u32 - 1),
+            Err(Error::SizeLimitExceeded { .. })
+        ));
+
+        /* The declared length must match */
+        let (header, body) = FrameHeader::read(&framed).unwrap();
+        let mut lying = Vec::new();
+        FrameHeader {
+            uncompressed_len: header.uncompressed_len + 1,
+            ..header
+        }
+        .write_to_bytes(&mut lying)
+        .unwrap();
+        lying.extend_from_slice(body);
+        assert!(matches!(d.decompress_framed(&lying, 16000), Err(Error::InvalidFrame(_))));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let framed = Builder::new().build_compressor().unwrap().compress_framed(&certificate_message()).unwrap();
+        let invalid = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut framed = framed.clone();
+            edit(&mut framed);
+            match FrameHeader::read(&framed) {
+                Err(Error::InvalidFrame(reason)) => reason,
+                other => panic!("Expected an invalid frame, got {:?}", other),
+            }
+        };
+        assert_eq!(invalid(&|x| x[0] = b'X'), "missing magic");
+        assert_eq!(invalid(&|x| x[3] = 2), "unsupported format version");
+        assert_eq!(invalid(&|x| x[4] |= 0x80), "unknown flags");
+        assert_eq!(invalid(&|x| x[4] = PASS2_DICTIONARY), "pass 2 dictionary without pass 2");
+        assert_eq!(invalid(&|x| x[9] = 7), "unknown codec");
+        assert_eq!(invalid(&|x| x.truncate(12)), "header is truncated");
+    }
+}
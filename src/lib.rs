@@ -1,49 +1,155 @@
 
-// #[cfg(all(feature ="nightly-features"))]
+// #[cfg(feature = "nightly-features")]
 // #[feature(custom_test_frameworks)]
 
 
-/* Expose internal functions to fuzzer */
-#[cfg(fuzzing)]
 pub mod tls;
-#[cfg(not(fuzzing))]
-mod tls;
 
-use rustls::compress::{CertCompressor, CertDecompressor};
-use bytes::{BufMut, Bytes};
-use std::io::Write;
+use rustls::compress::{CertCompressor, CertDecompressor, CompressionLevel};
+use rustls::pki_types::CertificateDer;
+use rustls::CertificateCompressionAlgorithm;
+use sha2::Digest;
+use std::sync::Arc;
 
+mod builder;
+pub mod ccadb;
+pub mod chain;
+mod der;
+mod error;
+pub mod frame;
 pub mod pass1;
 pub mod pass2;
+pub mod sct;
+#[cfg(test)]
+pub(crate) mod test_data;
+
+pub use builder::{Builder, Pass2Profile, Profile};
+pub use error::{CcadbReason, Error};
+
+/// The TLS certificate compression codepoint used when none is configured. Peers holding
+/// different dictionary versions must use different codepoints so that they never agree on an
+/// algorithm they cannot both decode.
+pub const DEFAULT_CODEPOINT: u16 = 9999;
+
+/// The length of the header added by [`Builder::integrity_check`]: the sender's pass 1 dictionary
+/// version, or 0 without pass 1, then the first 4 bytes of the SHA-256 hash of the Certificate
+/// message.
+pub const INTEGRITY_HEADER_LEN: usize = 8;
 
+fn checksum(cert_msg: &[u8]) -> [u8; 4] {
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&sha2::Sha256::digest(cert_msg)[..4]);
+    checksum
+}
+
+/// Runs pass 1, the optional SCT pass, then pass 2. Pass 1 or pass 2 may be disabled when built
+/// with a [`Builder`].
 #[derive(Debug)]
 pub struct Compressor {
-    p1: pass1::Compressor,
-    p2: pass2::Compressor,
+    p1: Option<pass1::Compressor>,
+    sct: Option<sct::Compressor>,
+    p2: Option<pass2::Compressor>,
+    codepoint: u16,
+    integrity_check: bool,
 }
 
 impl Compressor {
-    pub fn new(p1lookup: pass1::IdFunc) -> Self {
-        Compressor {
-            p1: pass1::Compressor::new(p1lookup),
-            p2: pass2::Compressor::new(),
-        }
+    /// Uses `dictionary` for pass 1 and the builtin dictionary for pass 2.
+    pub fn new(dictionary: Arc<dyn pass1::CertDictionary>) -> Self {
+        Self::from_parts(pass1::Compressor::new(dictionary), pass2::Compressor::new_from_builtin())
     }
 
+    /// Uses the builtin dictionaries for both passes.
     pub fn new_from_builtin() -> Self {
+        Self::from_parts(pass1::Compressor::new_builtin(), pass2::Compressor::new_from_builtin())
+    }
+
+    /// Combines separately configured passes, e.g. to select a different pass 2 codec.
+    pub fn from_parts(p1: pass1::Compressor, p2: pass2::Compressor) -> Self {
         Compressor {
-            p1: pass1::Compressor::new_builtin(),
-            p2: pass2::Compressor::new(),
+            p1: Some(p1),
+            sct: None,
+            p2: Some(p2),
+            codepoint: DEFAULT_CODEPOINT,
+            integrity_check: false,
+        }
+    }
+
+    /// Sets the codepoint advertised to rustls for this dictionary version.
+    pub fn with_codepoint(mut self, codepoint: u16) -> Self {
+        self.codepoint = codepoint;
+        self
+    }
+
+    pub fn codepoint(&self) -> u16 {
+        self.codepoint
+    }
+
+    /// What a `Decompressor` must be configured with to read this compressor's output.
+    pub fn profile(&self) -> Profile {
+        Profile {
+            codepoint: self.codepoint,
+            pass1: self.p1.as_ref().map(pass1::Compressor::metadata),
+            ocsp_staples: self.p1.as_ref().is_some_and(pass1::Compressor::ocsp_staples),
+            sct: self.sct.as_ref().map(|sct| sct.dictionary().id()),
+            pass2: self.p2.as_ref().map(|p2| Pass2Profile::new(p2.codec(), p2.dictionary())),
+            integrity_check: self.integrity_check,
         }
     }
 
     pub fn compress_to_bytes(
         &self,
         cert_msg: &[u8],
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let p1 = self.p1.compress_to_bytes(cert_msg)?;
-        let p2 = self.p2.compress_to_bytes(&p1)?;
-        Ok(p2)
+    ) -> Result<Vec<u8>, Error> {
+        self.compress_to_bytes_with_level(cert_msg, CompressionLevel::Amortized)
+    }
+
+    /// Pass 2 uses the codec settings the pass 2 `Compressor` has for `level`.
+    pub fn compress_to_bytes_with_level(
+        &self,
+        cert_msg: &[u8],
+        level: CompressionLevel,
+    ) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        if self.integrity_check {
+            let version = self.p1.as_ref().map_or(0, |p1| p1.metadata().version);
+            output.extend_from_slice(&version.to_be_bytes());
+            output.extend_from_slice(&checksum(cert_msg));
+        }
+        let p1 = match &self.p1 {
+            Some(p1) => p1.compress_to_bytes(cert_msg)?,
+            None => cert_msg.to_vec(),
+        };
+        let p1 = match &self.sct {
+            Some(sct) => sct.compress_to_bytes(&p1)?,
+            None => p1,
+        };
+        let p2 = match &self.p2 {
+            Some(p2) => p2.compress_to_bytes_with_level(&p1, level)?,
+            None => p1,
+        };
+        if output.is_empty() {
+            return Ok(p2);
+        }
+        output.extend_from_slice(&p2);
+        Ok(output)
+    }
+
+    /// Compresses a certificate chain, leaf first, which did not come from a TLS handshake. See
+    /// the [`chain`] module.
+    pub fn compress_chain(&self, chain: &[CertificateDer]) -> Result<Vec<u8>, Error> {
+        self.compress_to_bytes(&chain::to_message(chain)?)
+    }
+
+    /// Compresses `cert_msg` behind a [`frame::FrameHeader`] describing this compressor, for
+    /// storing outside TLS.
+    pub fn compress_framed(&self, cert_msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let uncompressed_len = u32::try_from(cert_msg.len()).map_err(|_| Error::LengthOverflow)?;
+        let codec = self.p2.as_ref().map(pass2::Compressor::codec);
+        let mut output = Vec::new();
+        frame::FrameHeader::new(&self.profile(), codec, uncompressed_len).write_to_bytes(&mut output)?;
+        output.extend_from_slice(&self.compress_to_bytes(cert_msg)?);
+        Ok(output)
     }
 }
 
@@ -51,69 +157,247 @@ impl CertCompressor for Compressor {
     fn compress(
         &self,
         input: Vec<u8>,
-        level: rustls::compress::CompressionLevel,
+        level: CompressionLevel,
     ) -> Result<Vec<u8>, rustls::compress::CompressionFailed> {
-        let res = self.compress_to_bytes(&input);
-        if res.is_err() {
-            Err(rustls::compress::CompressionFailed)
-        } else {
-            Ok(res.unwrap())
-        }
+        self.compress_to_bytes_with_level(&input, level)
+            .map_err(|_| rustls::compress::CompressionFailed)
     }
 
-    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
-        rustls::CertificateCompressionAlgorithm::Unknown(9999)
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::from(self.codepoint)
     }
 }
 
+/// Reverses pass 2, the SCT pass, then pass 1. Pass 1 or pass 2 may be disabled when built with
+/// a [`Builder`].
 #[derive(Debug)]
 pub struct Decompressor {
-    p1: pass1::Decompressor,
-    p2: pass2::Decompressor,
+    p1: Option<pass1::Decompressor>,
+    sct: Option<sct::Decompressor>,
+    p2: Option<pass2::Decompressor>,
+    codepoint: u16,
+    max_size: Option<u32>,
+    integrity_check: bool,
 }
 
 impl Decompressor {
-    pub fn new(p1_lookup: pass1::CertFunc) -> Self {
-        Decompressor {
-            p1: pass1::Decompressor::new(p1_lookup),
-            p2: pass2::Decompressor::new(),
-        }
+    /// Uses `dictionary` for pass 1 and the builtin dictionary for pass 2.
+    pub fn new(dictionary: Arc<dyn pass1::CertDictionary>) -> Self {
+        Self::from_parts(pass1::Decompressor::new(dictionary), pass2::Decompressor::new_from_builtin())
     }
 
+    /// Uses the builtin dictionaries for both passes.
     pub fn new_from_builtin() -> Self {
+        Self::from_parts(pass1::Decompressor::new_builtin(), pass2::Decompressor::new_from_builtin())
+    }
+
+    pub fn from_parts(p1: pass1::Decompressor, p2: pass2::Decompressor) -> Self {
         Decompressor {
-            p1: pass1::Decompressor::new_builtin(),
-            p2: pass2::Decompressor::new(),
+            p1: Some(p1),
+            sct: None,
+            p2: Some(p2),
+            codepoint: DEFAULT_CODEPOINT,
+            max_size: None,
+            integrity_check: false,
         }
     }
 
+    /// Sets the codepoint advertised to rustls for this dictionary version.
+    pub fn with_codepoint(mut self, codepoint: u16) -> Self {
+        self.codepoint = codepoint;
+        self
+    }
+
+    pub fn codepoint(&self) -> u16 {
+        self.codepoint
+    }
+
+    /// What a `Compressor` must be configured with for this decompressor to read its output.
+    pub fn profile(&self) -> Profile {
+        Profile {
+            codepoint: self.codepoint,
+            pass1: self.p1.as_ref().map(pass1::Decompressor::metadata),
+            ocsp_staples: self.p1.as_ref().is_some_and(pass1::Decompressor::ocsp_staples),
+            sct: self.sct.as_ref().map(|sct| sct.dictionary().id()),
+            pass2: self.p2.as_ref().map(|p2| Pass2Profile::new(p2.codec(), p2.dictionary())),
+            integrity_check: self.integrity_check,
+        }
+    }
+
+    /// Decompresses `comp_msg`, failing if the result would be larger than `max_size` or the
+    /// limit configured with [`Builder::max_decompressed_size`], whichever is smaller.
     pub fn decompress_to_bytes(
         &self,
         comp_msg: &[u8],
         max_size: u32,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let p2 = self.p2.decompress_to_bytes(comp_msg, max_size)?;
-        // TODO: Inefficient and needs the size limit enforcing
-        let p1 = self.p1.decompress_to_bytes(&p2)?;
-        Ok(p1)
+    ) -> Result<Vec<u8>, Error> {
+        let max_size = self.max_size.map_or(max_size, |limit| limit.min(max_size));
+        let mut expected = None;
+        let comp_msg = if self.integrity_check {
+            if comp_msg.len() < INTEGRITY_HEADER_LEN {
+                return Err(Error::TruncatedStream);
+            }
+            let (header, body) = comp_msg.split_at(INTEGRITY_HEADER_LEN);
+            let theirs = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let ours = self.p1.as_ref().map_or(0, |p1| p1.metadata().version);
+            if theirs != ours {
+                return Err(Error::DictionaryVersionMismatch { ours, theirs });
+            }
+            expected = Some(&header[4..]);
+            body
+        } else {
+            comp_msg
+        };
+        /* Identifiers and log codes are shorter than what they stand for, so the limit bounds every pass */
+        let p2 = match &self.p2 {
+            Some(p2) => p2.decompress_to_bytes(comp_msg, max_size)?,
+            None => comp_msg.to_vec(),
+        };
+        let p2 = match &self.sct {
+            Some(sct) => sct.decompress_to_bytes_with_limit(&p2, max_size as usize)?,
+            None => p2,
+        };
+        let output = match &self.p1 {
+            Some(p1) => p1.decompress_to_bytes_with_limit(&p2, max_size as usize)?,
+            None if p2.len() > max_size as usize => {
+                return Err(Error::SizeLimitExceeded {
+                    limit: max_size as usize,
+                })
+            }
+            None => p2,
+        };
+        if expected.is_some_and(|expected| expected != checksum(&output)) {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(output)
+    }
+
+    /// Decompresses the output of [`Compressor::compress_chain`]. `max_size` limits the chain and
+    /// its framing, 3 bytes per certificate and 4 for the chain.
+    pub fn decompress_chain(
+        &self,
+        comp_msg: &[u8],
+        max_size: u32,
+    ) -> Result<Vec<CertificateDer<'static>>, Error> {
+        chain::from_message(&self.decompress_to_bytes(comp_msg, max_size)?)
+    }
+
+    /// Decompresses the output of [`Compressor::compress_framed`], failing if the header
+    /// describes settings this decompressor does not share or its length is wrong.
+    pub fn decompress_framed(&self, framed: &[u8], max_size: u32) -> Result<Vec<u8>, Error> {
+        let (header, comp_msg) = frame::FrameHeader::read(framed)?;
+        header.check(&self.profile())?;
+        if header.uncompressed_len > max_size {
+            return Err(Error::SizeLimitExceeded {
+                limit: max_size as usize,
+            });
+        }
+        /* A longer message fails the size limit, a shorter one the comparison */
+        let output = self.decompress_to_bytes(comp_msg, header.uncompressed_len)?;
+        if output.len() != header.uncompressed_len as usize {
+            return Err(Error::InvalidFrame("uncompressed length does not match"));
+        }
+        Ok(output)
     }
 }
 
 impl CertDecompressor for Decompressor {
-    fn decompress(&self, input: &[u8], mut output: &mut [u8]) -> Result<(), rustls::compress::DecompressionFailed> {
-        let res = self.decompress_to_bytes(input,output.len() as u32);
-        if res.is_ok() {
-            let res = res.unwrap();
-            output.write_all(&res).unwrap();
-            Ok(())
-        } else {
-            Err(rustls::compress::DecompressionFailed)
+    /// `output` is sized to the length the peer declared, which RFC 8879 requires the message to
+    /// match exactly.
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), rustls::compress::DecompressionFailed> {
+        let res = self
+            .decompress_to_bytes(input, output.len() as u32)
+            .map_err(|_| rustls::compress::DecompressionFailed)?;
+        if res.len() != output.len() {
+            return Err(rustls::compress::DecompressionFailed);
         }
+        output.copy_from_slice(&res);
+        Ok(())
     }
 
-    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
-        rustls::CertificateCompressionAlgorithm::Unknown(9999)
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::from(self.codepoint)
+    }
+}
+
+/* rustls configs hold `&'static` references, and they live for the rest of the process anyway */
+fn leak_unique<T: 'static>(items: Vec<T>, codepoint: impl Fn(&T) -> u16) -> Result<Vec<&'static T>, Error> {
+    let mut seen = std::collections::HashSet::new();
+    for item in &items {
+        let codepoint = codepoint(item);
+        if !matches!(
+            CertificateCompressionAlgorithm::from(codepoint),
+            CertificateCompressionAlgorithm::Unknown(_)
+        ) {
+            return Err(Error::ReservedCodepoint(codepoint));
+        }
+        if !seen.insert(codepoint) {
+            return Err(Error::DuplicateCodepoint(codepoint));
+        }
+    }
+    Ok(items.into_iter().map(|x| &*Box::leak(Box::new(x))).collect())
+}
+
+/// Prepares one `Decompressor` per dictionary version for `ClientConfig::cert_decompressors`.
+///
+/// rustls advertises the codepoints in the order given and the peer picks the first one it
+/// shares, so newer versions should come first.
+pub fn static_decompressors(
+    decompressors: Vec<Decompressor>,
+) -> Result<Vec<&'static dyn CertDecompressor>, Error> {
+    Ok(leak_unique(decompressors, Decompressor::codepoint)?
+        .into_iter()
+        .map(|x| x as &dyn CertDecompressor)
+        .collect())
+}
+
+/// Prepares one `Compressor` per dictionary version for `ServerConfig::cert_compressors`, in
+/// order of preference.
+pub fn static_compressors(
+    compressors: Vec<Compressor>,
+) -> Result<Vec<&'static dyn CertCompressor>, Error> {
+    Ok(leak_unique(compressors, Compressor::codepoint)?
+        .into_iter()
+        .map(|x| x as &dyn CertCompressor)
+        .collect())
+}
+
+/// The compressors and decompressors for a peer which both sends and receives certificates.
+pub type StaticCodecs = (Vec<&'static dyn CertCompressor>, Vec<&'static dyn CertDecompressor>);
+
+/// Prepares both directions of each dictionary version, in order of preference, for mutual TLS.
+///
+/// Both `ClientConfig` and `ServerConfig` have `cert_compressors` and `cert_decompressors`: a
+/// server decompresses the client's certificates and a client compresses its own.
+pub fn static_codecs(builders: &[Builder]) -> Result<StaticCodecs, Error> {
+    let compressors = builders
+        .iter()
+        .map(Builder::build_compressor)
+        .collect::<Result<_, _>>()?;
+    let decompressors = builders
+        .iter()
+        .map(Builder::build_decompressor)
+        .collect::<Result<_, _>>()?;
+    Ok((static_compressors(compressors)?, static_decompressors(decompressors)?))
+}
+
+/// Parses `CODEPOINT=PATH` arguments, one per extra pass 1 dictionary version, into a `Builder`
+/// for each, in order of preference. The default `Builder` comes last, for peers which have not
+/// upgraded.
+pub fn dictionary_builders(specs: &[String]) -> Result<Vec<Builder>, Error> {
+    let mut builders = Vec::with_capacity(specs.len() + 1);
+    for spec in specs {
+        let (codepoint, path) = spec
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidDictionary(format!("Expected CODEPOINT=PATH, got {:?}", spec)))?;
+        let codepoint = codepoint
+            .parse()
+            .map_err(|_| Error::InvalidDictionary(format!("Invalid codepoint {:?}", codepoint)))?;
+        let dictionary = pass1::LoadedDictionary::from_file(path)?;
+        builders.push(Builder::new().pass1_dictionary(Arc::new(dictionary)).codepoint(codepoint));
     }
+    builders.push(Builder::new());
+    Ok(builders)
 }
 
 #[cfg(test)]
@@ -143,7 +427,7 @@ mod tests {
         2b191041641c237f11a5d65c844f0404849938712b959ed685bc5c5dd645ed19909473402926dcb4
         0e3469a15941e8e2cca84bb6084636a00000";
 
-    const COMPRESSED_MESSAGE : &[u8] = &[0x41,0xdc,0x13,0x00,0x30,0xc3,0xd3,0xcd,0x3f,0x75,0x13,0xf4,0x6a,0x6b,0x60,0x03,0xe9,0x4f,0x61,0xf0,0xa6,0xae,0xa7,0x1e,0x86,0x19,0x87,0xff,0xe8,0xd5,0xf8,0x11,0x07,0x98,0xe0,0x3b,0xf4,0x05,0x32,0x0b,0x0c,0xc3,0xd2,0x6d,0x0e,0xc4,0x3e,0x23,0x28,0xb5,0x02,0x95,0xde,0x6e,0x04,0x41,0xd8,0x17,0x08,0x7b,0x0e,0x0a,0xb0,0xc7,0xa0,0x00,0x3d,0xdd,0x87,0x22,0x30,0x82,0xb0,0x62,0xb9,0xb4,0x67,0x25,0x4d,0x53,0x5b,0x57,0x06,0xaa,0x75,0xdf,0xdf,0x11,0xe9,0x8d,0x7e,0x09,0x18,0x70,0x1a,0xd9,0x12,0xb3,0x92,0xef,0x0c,0x30,0x4c,0x47,0x80,0x00,0x89,0x48,0x07,0x68,0x70,0xd4,0x19,0xc3,0xd9,0x10,0x67,0x47,0x22,0x27,0x60,0x97,0x5e,0x5a,0x36,0x06,0xab,0xe0,0x24,0xa9,0x44,0x61,0xe3,0x98,0xc0,0x84,0xf4,0xb8,0x24,0x43,0x80,0x2e,0x71,0x50,0x06,0x82,0x5c,0x0c,0x24,0x15,0x40,0x22,0x92,0x01,0x50,0x21,0x01,0x55,0x0f,0xc1,0xc8,0x09,0xb2,0x07,0xe0,0x24,0xb2,0x03,0x56,0x17,0x8e,0x2e,0x89,0xa8,0xba,0x2a,0x80,0x02,0x58,0xf4,0x0a,0x00,0x8e,0xd0,0x02,0x98,0x09,0xd2,0x99,0xb4,0x48,0x01,0xfd,0x5e,0xe8,0xa4,0xc5,0x83,0xbb,0x99,0x1f,0x27,0xfa,0x0a,0x3c,0xd8,0xfc,0xdf,0x41,0xd6,0x20,0x8f,0xbd,0xfc,0x54,0x67,0x7a,0xd5,0x90,0xf4,0xbc,0x44,0x26,0x89,0x48,0x7d,0xcf,0x88,0xc6,0xb1,0xb1,0xf0,0x4a,0xc7,0xcc,0x3f,0x72,0xcd,0xa9,0x06,0xc6,0x67,0xef,0x98,0xb5,0x28,0x7a,0x1b,0x09,0x5d,0xa9,0xce,0x96,0xc1,0x43,0x9b,0x87,0xec,0x17,0x36,0x9e,0x08,0xf3,0x9a,0xc4,0x95,0x9d,0x5a,0x85,0x4e,0xe6,0xe9,0x5c,0xff,0xe8,0x12,0xb0,0xfc,0x83,0x2a,0x6a,0xf5,0xe7,0xda,0xdd,0x3f,0xc4,0xe5,0xfb,0x2a,0x2d,0xcf,0xe0,0xdf,0x5f,0xb4,0x1d,0xbf,0xcf,0xba,0xf0,0xb6,0xdc,0x4e,0x92,0x29,0x7d,0x72,0xa6,0x7c,0xa4,0x36,0xf3,0x9a,0xad,0x6f,0x30,0x9f,0x9f,0x6b,0xdd,0x8d,0x9c,0x97,0x76,0x0d,0xaa,0xcc,0x57,0x6d,0x0e,0xf3,0x50,0xdf,0x85,0x63,0x8f,0xcc,0xdf,0x58,0x61,0x53,0xc8,0xff,0x25,0xa6,0x93,0x9f,0xdc,0x6d,0x79,0x34,0xcb,0x2f,0xdd,0x60,0x8d,0x3b,0xc8,0x20,0xbc,0xbb,0x69,0xec,0x27,0xf8,0xb4,0x30,0x9f,0x72,0xa7,0x2b,0x87,0xf8,0x64,0xe0,0x50,0xf8,0x71,0xe9,0x14,0x5b,0x4a,0xd7,0x03,0xc1,0x75,0x13,0x5d,0x41,0xfa,0x97,0xde,0xc5,0xeb,0x1b,0xb1,0x36,0xd2,0xe7,0xff,0xe6,0x49,0x9e,0x49,0x7d,0x9d,0x20,0x7f,0x89,0x55,0xf5,0x51,0xfe,0x12,0x83,0xc8,0xd3,0xd1,0x75,0x2a,0x82,0xc7,0x3d,0x56,0x7e,0x4b,0x7a,0x11,0xa7,0x9a,0x9a,0xfe,0xdf,0xbf,0x18,0x9f,0x55,0x5c,0x0c,0x27,0x0b,0x53,0xeb,0x90,0x28,0x32,0xa2,0xe9,0xf9,0x15,0x1d,0x4f,0xce,0xf5,0x22,0x28,0x0c,0xc1,0x83,0x05,0x08,0x03,0x28,0x40,0x68,0x00,0x23,0x8e,0x3a,0x0b,0x30,0xc1,0xf0,0x7f,0x0c,0x43,0x11,0x42,0x1f,0x10,0x60,0x59,0x02,0x8b,0x52,0x9e,0x5a,0x0e,0x87,0x09,0x04,0x2a,0x14,0x9e,0xd9,0x08,0xa0,0x37,0xa1,0xd9,0x5c,0x5c,0x04,0x40,0x0d,0x30,0xce,0xc6,0xd8,0x93,0xf8,0x48,0x03,0x27,0x45,0xe8,0x8b,0x6c,0xbb,0x2d,0x47,0xcc,0xce,0xcf,0x17,0x5c,0x68,0xd6,0xb5,0x04,0x42,0xb8,0x26,0x86,0x71,0x03,0xce,0x3c,0x76,0x76,0xc5,0x45,0xae,0x55,0x37,0x97,0xd3,0x33,0x76,0x34,0x94,0x67,0x42,0xb3,0xdd,0x95,0xec,0x07,0xc7,0x80,0xf3,0xee,0x19,0x18,0xc6,0xcc,0x81,0x29,0x10,0x59,0xf3,0x00,0x5c,0xc2,0xf1,0xf2,0xfb,0x09,0xca,0x8a,0xb1,0xbf,0x8a,0x55,0x02,0x13,0x22,0x2c,0x88,0x9a,0x91,0x90,0x12,0x4e,0x5c,0xb8,0xad,0x04,0x38,0xcf,0x2e,0x65,0xc1,0x98,0x00,0x43,0x41,0x76,0xd0,0x6c,0xba,0x2a,0x61,0x8c,0x1e,0xd0,0x02,0x6a,0x1c,0x0f,0xcd,0xa7,0x87,0x11,0xb8,0xbf,0x0b,0x86,0xd3,0xca,0xe1,0x30,0x06,0x5f,0x4b,0x47,0x30,0x04,0xcb,0xff,0x8a,0xe5,0x7f,0x82,0x3e,0x40,0x29,0xd0,0xbb,0xb3,0x17,0x82,0xae,0xde,0xe6,0x3d,0x77,0xd4,0x6b,0xb5,0x7b,0xe9,0x2c,0xdb,0x00,0xa9,0xd4,0x24,0x6b,0xeb,0xcd,0xbd,0x43,0x7b,0x33,0x77,0x93,0x78,0xa1,0xe5,0x4e,0x08,0x82,0xab,0x27,0xa2,0xbb,0x58,0x20,0x08,0x43,0x21,0x53,0x60,0x8c,0x08,0x87,0xf6,0x10,0x62,0xcb,0x97,0x6a,0x3b,0xe6,0xde,0x2e,0xaf,0x30,0x66,0x7a,0xe0,0xd2,0xaf,0xd4,0xc5,0xa6,0x4b,0xfe,0x4f,0x2b,0x9b,0xf6,0x2d,0x09,0x34,0xfc,0xcc,0x44,0x44,0xa0,0x6f,0x13,0xc5,0x27,0xf8,0xfa,0x5f,0xdc,0xd0,0x89,0x11,0x5d,0xab,0xfb,0xb9,0x21,0x9e,0x3c,0x3a,0x1d,0xf0,0xad,0xf3,0x7f,0x56,0x42,0xe9,0x59,0xeb,0xa1,0x89,0x03,0x03,0x3a,0xac,0xe5,0x98,0x9a,0xac,0xab,0xb0,0x9e,0x67,0x53,0x09,0x3c,0xf1,0x7f,0x14,0xad,0xd0,0x1b,0xb6,0x87,0x5f,0x8e,0x6f,0x43,0x0c,0x0a,0x09,0xb1,0x0f,0x7f,0x5d,0xe1,0x02,0xa1,0xe7,0xc8,0x6a,0xd6,0xae,0xa1,0x83,0x3b,0x7d,0x1c,0xe2,0xed,0x2b,0x9a,0x1d,0x0d,0xcf,0xbb,0x6f,0x7d,0x94,0x19,0x57,0xbc,0x63,0xdd,0xeb,0xc3,0xf2,0x34,0xdd,0x65,0x28,0xb5,0xf3,0x1f,0x9e,0x1e,0xf8,0x4d,0xff,0x4b,0xeb,0x2b,0xad,0xfd,0x35,0x87,0x8b,0x26,0x4f,0x18,0x9f,0xd0,0x97,0xb5,0x24,0x8b,0x57,0xf1,0xea,0xdc,0x78,0x8d,0xb3,0x0a,0xfc,0xbb,0xe6,0x52,0x36,0x08,0xdd,0x17,0x84,0x21,0x9f,0xa2,0x85,0xc7,0x0d,0xbc,0x9b,0xd8,0x0d,0x9a,0x67,0xf6,0x6e,0x93,0x63,0xc0,0xb4,0xf8,0xd5,0x89,0xcb,0xa2,0x6d,0x1c,0xf1,0xfb,0xdf,0x7d,0x54,0x1f,0x7d,0xff,0xfa,0xdc,0xec,0x8c,0x16,0xaf,0xac,0xc1,0xaa,0xbc,0x8c,0xb4,0xf9,0xe7,0x63,0x71,0x75,0x11,0x93,0xd5,0xc5,0x49,0xcd,0x32,0x3f,0x82,0x74,0x1c,0xfe,0xb6,0x29,0x2f,0xb5,0x4c,0x1e,0xd5,0x5e,0x51,0x61,0x94,0xfb,0x54,0x43,0xda,0x6e,0xfd,0xfe,0x2a,0x3c,0x79,0x35,0xeb,0x88,0xec,0x2f,0x97,0x7b,0x65,0xe7,0xd4,0xfc,0x07,0x44,0x12,0x05,0xce,0x50,0x05,0xb7,0x4e,0x2c,0x48,0x37,0x3e,0x20,0x1b,0x5e,0x69,0x38,0x62,0x11,0x62,0x7e,0x56,0x9b,0xca,0x18,0xb5,0x9b,0x8e,0xe1,0x48,0x8b,0x30,0x3c,0xff,0xed,0xdc,0x00,0xff,0x0f,0x69,0xf8,0xb9,0x99,0x59,0xbf,0x59,0xff,0xae,0xb2,0x96,0x48,0xcc,0xcf,0xbf,0x79,0xc2,0x39,0x95,0xf1,0x4a,0xc2,0xeb,0xaa,0x97,0x29,0x2e,0x72,0xf9,0xcb,0x7f,0x6f,0x03,0xe9,0xcb,0xef,0x1f,0x88,0x69,0x86,0x3c,0x67,0x97,0x7c,0xe8,0x4c,0x7f,0xbc,0xe5,0x3c,0x46,0x6c,0xeb,0xa2,0x14,0x3f,0xa1,0xfd,0xce,0x92,0x25,0x9d,0xfe,0x32,0xfd,0x94,0x96,0x47,0xb8,0x04,0x75,0x0b,0xda,0x77,0xb4,0x17,0xa7,0x56,0xe3,0xbf,0x3e,0x3d,0xee,0x15,0xc6,0xc6,0x77,0x6a,0x0f,0x0d,0xe5,0x90,0x79,0xfd,0xef,0xd6,0xcb,0x78,0x89,0x11,0xf7,0xb4,0x07,0x72,0x5d,0x3b,0x0e,0x1c,0x9a,0xbb,0x94,0x8d,0xb4,0x6c,0xf2,0x83,0x9c,0xc5,0x25,0xfe,0xf3,0x30,0x03,0x4f,0x70,0x97,0x17,0x34,0xc4,0xd0,0xff,0xd8,0x4b,0x08,0x32,0x00,0x03];
+    const COMPRESSED_MESSAGE : &[u8] = &[0x00,0x41,0xdc,0x13,0x00,0x30,0xc3,0xd3,0xcd,0x3f,0x75,0x13,0xf4,0x6a,0x6b,0x60,0x03,0xe9,0x4f,0x61,0xf0,0xa6,0xae,0xa7,0x1e,0x86,0x19,0x87,0xff,0xe8,0xd5,0xf8,0x11,0x07,0x98,0xe0,0x3b,0xf4,0x05,0x32,0x0b,0x0c,0xc3,0xd2,0x6d,0x0e,0xc4,0x3e,0x23,0x28,0xb5,0x02,0x95,0xde,0x6e,0x04,0x41,0xd8,0x17,0x08,0x7b,0x0e,0x0a,0xb0,0xc7,0xa0,0x00,0x3d,0xdd,0x87,0x22,0x30,0x82,0xb0,0x62,0xb9,0xb4,0x67,0x25,0x4d,0x53,0x5b,0x57,0x06,0xaa,0x75,0xdf,0xdf,0x11,0xe9,0x8d,0x7e,0x09,0x18,0x70,0x1a,0xd9,0x12,0xb3,0x92,0xef,0x0c,0x30,0x4c,0x47,0x80,0x00,0x89,0x48,0x07,0x68,0x70,0xd4,0x19,0xc3,0xd9,0x10,0x67,0x47,0x22,0x27,0x60,0x97,0x5e,0x5a,0x36,0x06,0xab,0xe0,0x24,0xa9,0x44,0x61,0xe3,0x98,0xc0,0x84,0xf4,0xb8,0x24,0x43,0x80,0x2e,0x71,0x50,0x06,0x82,0x5c,0x0c,0x24,0x15,0x40,0x22,0x92,0x01,0x50,0x21,0x01,0x55,0x0f,0xc1,0xc8,0x09,0xb2,0x07,0xe0,0x24,0xb2,0x03,0x56,0x17,0x8e,0x2e,0x89,0xa8,0xba,0x2a,0x80,0x02,0x58,0xf4,0x0a,0x00,0x8e,0xd0,0x02,0x98,0x09,0xd2,0x99,0xb4,0x48,0x01,0xfd,0x5e,0xe8,0xa4,0xc5,0x83,0xbb,0x99,0x1f,0x27,0xfa,0x0a,0x3c,0xd8,0xfc,0xdf,0x41,0xd6,0x20,0x8f,0xbd,0xfc,0x54,0x67,0x7a,0xd5,0x90,0xf4,0xbc,0x44,0x26,0x89,0x48,0x7d,0xcf,0x88,0xc6,0xb1,0xb1,0xf0,0x4a,0xc7,0xcc,0x3f,0x72,0xcd,0xa9,0x06,0xc6,0x67,0xef,0x98,0xb5,0x28,0x7a,0x1b,0x09,0x5d,0xa9,0xce,0x96,0xc1,0x43,0x9b,0x87,0xec,0x17,0x36,0x9e,0x08,0xf3,0x9a,0xc4,0x95,0x9d,0x5a,0x85,0x4e,0xe6,0xe9,0x5c,0xff,0xe8,0x12,0xb0,0xfc,0x83,0x2a,0x6a,0xf5,0xe7,0xda,0xdd,0x3f,0xc4,0xe5,0xfb,0x2a,0x2d,0xcf,0xe0,0xdf,0x5f,0xb4,0x1d,0xbf,0xcf,0xba,0xf0,0xb6,0xdc,0x4e,0x92,0x29,0x7d,0x72,0xa6,0x7c,0xa4,0x36,0xf3,0x9a,0xad,0x6f,0x30,0x9f,0x9f,0x6b,0xdd,0x8d,0x9c,0x97,0x76,0x0d,0xaa,0xcc,0x57,0x6d,0x0e,0xf3,0x50,0xdf,0x85,0x63,0x8f,0xcc,0xdf,0x58,0x61,0x53,0xc8,0xff,0x25,0xa6,0x93,0x9f,0xdc,0x6d,0x79,0x34,0xcb,0x2f,0xdd,0x60,0x8d,0x3b,0xc8,0x20,0xbc,0xbb,0x69,0xec,0x27,0xf8,0xb4,0x30,0x9f,0x72,0xa7,0x2b,0x87,0xf8,0x64,0xe0,0x50,0xf8,0x71,0xe9,0x14,0x5b,0x4a,0xd7,0x03,0xc1,0x75,0x13,0x5d,0x41,0xfa,0x97,0xde,0xc5,0xeb,0x1b,0xb1,0x36,0xd2,0xe7,0xff,0xe6,0x49,0x9e,0x49,0x7d,0x9d,0x20,0x7f,0x89,0x55,0xf5,0x51,0xfe,0x12,0x83,0xc8,0xd3,0xd1,0x75,0x2a,0x82,0xc7,0x3d,0x56,0x7e,0x4b,0x7a,0x11,0xa7,0x9a,0x9a,0xfe,0xdf,0xbf,0x18,0x9f,0x55,0x5c,0x0c,0x27,0x0b,0x53,0xeb,0x90,0x28,0x32,0xa2,0xe9,0xf9,0x15,0x1d,0x4f,0xce,0xf5,0x22,0x28,0x0c,0xc1,0x83,0x05,0x08,0x03,0x28,0x40,0x68,0x00,0x23,0x8e,0x3a,0x0b,0x30,0xc1,0xf0,0x7f,0x0c,0x43,0x11,0x42,0x1f,0x10,0x60,0x59,0x02,0x8b,0x52,0x9e,0x5a,0x0e,0x87,0x09,0x04,0x2a,0x14,0x9e,0xd9,0x08,0xa0,0x37,0xa1,0xd9,0x5c,0x5c,0x04,0x40,0x0d,0x30,0xce,0xc6,0xd8,0x93,0xf8,0x48,0x03,0x27,0x45,0xe8,0x8b,0x6c,0xbb,0x2d,0x47,0xcc,0xce,0xcf,0x17,0x5c,0x68,0xd6,0xb5,0x04,0x42,0xb8,0x26,0x86,0x71,0x03,0xce,0x3c,0x76,0x76,0xc5,0x45,0xae,0x55,0x37,0x97,0xd3,0x33,0x76,0x34,0x94,0x67,0x42,0xb3,0xdd,0x95,0xec,0x07,0xc7,0x80,0xf3,0xee,0x19,0x18,0xc6,0xcc,0x81,0x29,0x10,0x59,0xf3,0x00,0x5c,0xc2,0xf1,0xf2,0xfb,0x09,0xca,0x8a,0xb1,0xbf,0x8a,0x55,0x02,0x13,0x22,0x2c,0x88,0x9a,0x91,0x90,0x12,0x4e,0x5c,0xb8,0xad,0x04,0x38,0xcf,0x2e,0x65,0xc1,0x98,0x00,0x43,0x41,0x76,0xd0,0x6c,0xba,0x2a,0x61,0x8c,0x1e,0xd0,0x02,0x6a,0x1c,0x0f,0xcd,0xa7,0x87,0x11,0xb8,0xbf,0x0b,0x86,0xd3,0xca,0xe1,0x30,0x06,0x5f,0x4b,0x47,0x30,0x04,0xcb,0xff,0x8a,0xe5,0x7f,0x82,0x3e,0x40,0x29,0xd0,0xbb,0xb3,0x17,0x82,0xae,0xde,0xe6,0x3d,0x77,0xd4,0x6b,0xb5,0x7b,0xe9,0x2c,0xdb,0x00,0xa9,0xd4,0x24,0x6b,0xeb,0xcd,0xbd,0x43,0x7b,0x33,0x77,0x93,0x78,0xa1,0xe5,0x4e,0x08,0x82,0xab,0x27,0xa2,0xbb,0x58,0x20,0x08,0x43,0x21,0x53,0x60,0x8c,0x08,0x87,0xf6,0x10,0x62,0xcb,0x97,0x6a,0x3b,0xe6,0xde,0x2e,0xaf,0x30,0x66,0x7a,0xe0,0xd2,0xaf,0xd4,0xc5,0xa6,0x4b,0xfe,0x4f,0x2b,0x9b,0xf6,0x2d,0x09,0x34,0xfc,0xcc,0x44,0x44,0xa0,0x6f,0x13,0xc5,0x27,0xf8,0xfa,0x5f,0xdc,0xd0,0x89,0x11,0x5d,0xab,0xfb,0xb9,0x21,0x9e,0x3c,0x3a,0x1d,0xf0,0xad,0xf3,0x7f,0x56,0x42,0xe9,0x59,0xeb,0xa1,0x89,0x03,0x03,0x3a,0xac,0xe5,0x98,0x9a,0xac,0xab,0xb0,0x9e,0x67,0x53,0x09,0x3c,0xf1,0x7f,0x14,0xad,0xd0,0x1b,0xb6,0x87,0x5f,0x8e,0x6f,0x43,0x0c,0x0a,0x09,0xb1,0x0f,0x7f,0x5d,0xe1,0x02,0xa1,0xe7,0xc8,0x6a,0xd6,0xae,0xa1,0x83,0x3b,0x7d,0x1c,0xe2,0xed,0x2b,0x9a,0x1d,0x0d,0xcf,0xbb,0x6f,0x7d,0x94,0x19,0x57,0xbc,0x63,0xdd,0xeb,0xc3,0xf2,0x34,0xdd,0x65,0x28,0xb5,0xf3,0x1f,0x9e,0x1e,0xf8,0x4d,0xff,0x4b,0xeb,0x2b,0xad,0xfd,0x35,0x87,0x8b,0x26,0x4f,0x18,0x9f,0xd0,0x97,0xb5,0x24,0x8b,0x57,0xf1,0xea,0xdc,0x78,0x8d,0xb3,0x0a,0xfc,0xbb,0xe6,0x52,0x36,0x08,0xdd,0x17,0x84,0x21,0x9f,0xa2,0x85,0xc7,0x0d,0xbc,0x9b,0xd8,0x0d,0x9a,0x67,0xf6,0x6e,0x93,0x63,0xc0,0xb4,0xf8,0xd5,0x89,0xcb,0xa2,0x6d,0x1c,0xf1,0xfb,0xdf,0x7d,0x54,0x1f,0x7d,0xff,0xfa,0xdc,0xec,0x8c,0x16,0xaf,0xac,0xc1,0xaa,0xbc,0x8c,0xb4,0xf9,0xe7,0x63,0x71,0x75,0x11,0x93,0xd5,0xc5,0x49,0xcd,0x32,0x3f,0x82,0x74,0x1c,0xfe,0xb6,0x29,0x2f,0xb5,0x4c,0x1e,0xd5,0x5e,0x51,0x61,0x94,0xfb,0x54,0x43,0xda,0x6e,0xfd,0xfe,0x2a,0x3c,0x79,0x35,0xeb,0x88,0xec,0x2f,0x97,0x7b,0x65,0xe7,0xd4,0xfc,0x07,0x44,0x12,0x05,0xce,0x50,0x05,0xb7,0x4e,0x2c,0x48,0x37,0x3e,0x20,0x1b,0x5e,0x69,0x38,0x62,0x11,0x62,0x7e,0x56,0x9b,0xca,0x18,0xb5,0x9b,0x8e,0xe1,0x48,0x8b,0x30,0x3c,0xff,0xed,0xdc,0x00,0xff,0x0f,0x69,0xf8,0xb9,0x99,0x59,0xbf,0x59,0xff,0xae,0xb2,0x96,0x48,0xcc,0xcf,0xbf,0x79,0xc2,0x39,0x95,0xf1,0x4a,0xc2,0xeb,0xaa,0x97,0x29,0x2e,0x72,0xf9,0xcb,0x7f,0x6f,0x03,0xe9,0xcb,0xef,0x1f,0x88,0x69,0x86,0x3c,0x67,0x97,0x7c,0xe8,0x4c,0x7f,0xbc,0xe5,0x3c,0x46,0x6c,0xeb,0xa2,0x14,0x3f,0xa1,0xfd,0xce,0x92,0x25,0x9d,0xfe,0x32,0xfd,0x94,0x96,0x47,0xb8,0x04,0x75,0x0b,0xda,0x77,0xb4,0x17,0xa7,0x56,0xe3,0xbf,0x3e,0x3d,0xee,0x15,0xc6,0xc6,0x77,0x6a,0x0f,0x0d,0xe5,0x90,0x79,0xfd,0xef,0xd6,0xcb,0x78,0x89,0x11,0xf7,0xb4,0x07,0x72,0x5d,0x3b,0x0e,0x1c,0x9a,0xbb,0x94,0x8d,0xb4,0x6c,0xf2,0x83,0x9c,0xc5,0x25,0xfe,0xf3,0x30,0x03,0x4f,0x70,0x97,0x17,0x34,0xc4,0xd0,0xff,0xd8,0x4b,0x08,0x32,0x00,0x03];
 
     #[test]
     fn compressor_happy() {
@@ -160,8 +444,9 @@ mod tests {
 
     #[test]
     fn test_decompressor() {
-        let c = Decompressor::new_from_builtin();
-        c.decompress_to_bytes(&COMPRESSED_MESSAGE, 16000)
+        /* Compressed without a pass 2 dictionary, so that the vector survives changes to it */
+        let c = Decompressor::from_parts(pass1::Decompressor::new_builtin(), pass2::Decompressor::new());
+        c.decompress_to_bytes(COMPRESSED_MESSAGE, 16000)
             .expect("Decompression succeeds");
     }
 
@@ -181,6 +466,121 @@ mod tests {
         assert_eq!(cert_bytes, round_trip);
     }
 
+    #[test]
+    fn round_trip_zstd() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let c = Compressor::from_parts(
+            pass1::Compressor::new_builtin(),
+            pass2::Compressor::new_with_codec(pass2::Codec::zstd(), Some(pass2::Dictionary::builtin())),
+        );
+        let out = c
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let c = Decompressor::from_parts(
+            pass1::Decompressor::new_builtin(),
+            pass2::Decompressor::new_with_codec(pass2::Codec::zstd(), Some(pass2::Dictionary::builtin())),
+        );
+        let round_trip = c
+            .decompress_to_bytes(&out, 16000)
+            .expect("Decompression succeeds");
+        assert_eq!(cert_bytes, round_trip);
+    }
+
+    #[test]
+    fn codepoints_are_configurable() {
+        let c = Compressor::new_from_builtin();
+        assert_eq!(c.algorithm(), CertificateCompressionAlgorithm::Unknown(DEFAULT_CODEPOINT));
+        let c = c.with_codepoint(0xfe01);
+        assert_eq!(c.algorithm(), CertificateCompressionAlgorithm::Unknown(0xfe01));
+        let d = Decompressor::new_from_builtin().with_codepoint(0xfe01);
+        assert_eq!(d.algorithm(), c.algorithm());
+    }
+
+    #[test]
+    fn multiple_versions_register() {
+        let decompressors = static_decompressors(vec![
+            Decompressor::new_from_builtin().with_codepoint(0xfe02),
+            Decompressor::new_from_builtin().with_codepoint(0xfe01),
+        ])
+        .expect("Distinct codepoints");
+        let advertised: Vec<_> = decompressors.iter().map(|x| x.algorithm()).collect();
+        assert_eq!(
+            advertised,
+            [
+                CertificateCompressionAlgorithm::Unknown(0xfe02),
+                CertificateCompressionAlgorithm::Unknown(0xfe01)
+            ]
+        );
+
+        assert!(matches!(
+            static_compressors(vec![
+                Compressor::new_from_builtin().with_codepoint(0xfe01),
+                Compressor::new_from_builtin().with_codepoint(0xfe01),
+            ]),
+            Err(Error::DuplicateCodepoint(0xfe01))
+        ));
+        /* Brotli's codepoint */
+        assert!(matches!(
+            static_decompressors(vec![Decompressor::new_from_builtin().with_codepoint(2)]),
+            Err(Error::ReservedCodepoint(2))
+        ));
+    }
+
+    #[test]
+    fn client_certificates() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let mut with_context = vec![0x08];
+        with_context.extend_from_slice(&[0x3c; 8]);
+        with_context.extend_from_slice(&cert_bytes[1..]);
+
+        let (compressors, decompressors) = static_codecs(&[
+            Builder::new().codepoint(0xfe02),
+            Builder::new().codepoint(0xfe01).pass2_codec(pass2::Codec::zstd()),
+        ])
+        .expect("Distinct codepoints");
+        assert_eq!(compressors.len(), 2);
+        for (c, d) in compressors.iter().zip(&decompressors) {
+            assert_eq!(c.algorithm(), d.algorithm());
+            let out = c.compress(with_context.clone(), CompressionLevel::Interactive).unwrap();
+            let mut round_trip = vec![0; with_context.len()];
+            d.decompress(&out, &mut round_trip).expect("Decompression succeeds");
+            assert_eq!(with_context, round_trip);
+        }
+        assert!(matches!(
+            static_codecs(&[Builder::new(), Builder::new()]),
+            Err(Error::DuplicateCodepoint(DEFAULT_CODEPOINT))
+        ));
+    }
+
+    #[test]
+    fn builders_from_arguments() {
+        let dictionary = pass1::LoadedDictionary::from_entries(
+            "2024-01-01".to_string(),
+            vec![(vec![0xff, 0x00, 0x00], vec![0x30, 0x01, 0x00])],
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("abridged-certs-{}.json", std::process::id()));
+        std::fs::write(&path, dictionary.to_json()).unwrap();
+        let builders = dictionary_builders(&[format!("65034={}", path.display())]);
+        std::fs::remove_file(&path).unwrap();
+        let builders = builders.expect("Valid arguments");
+        assert_eq!(builders.len(), 2);
+        assert_eq!(builders[0].profile().codepoint, 65034);
+        assert_eq!(builders[0].profile().pass1, Some(pass1::CertDictionary::metadata(&dictionary)));
+        assert_eq!(builders[1].profile(), Builder::new().profile());
+
+        for spec in ["65034", "x=dictionary.json"] {
+            assert!(matches!(
+                dictionary_builders(&[spec.to_string()]),
+                Err(Error::InvalidDictionary(_))
+            ));
+        }
+    }
+
     #[test]
     fn size_limits() {
         let mut cert_hex: String = String::from(CERTMSG);
@@ -195,9 +595,47 @@ mod tests {
             .decompress_to_bytes(&out, 100)
             .expect_err("Shouldn't be enough space!");
     }
+
+    #[test]
+    fn size_limit_covers_pass1() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let cert = cert_bytes[7..cert_bytes.len() - 2].to_vec();
+        let dictionary: Arc<dyn pass1::CertDictionary> = Arc::new(
+            pass1::LoadedDictionary::from_entries("2024-01-01".into(), vec![(vec![0xff, 0x12, 0x34], cert)]).unwrap(),
+        );
+        let out = Compressor::new(dictionary.clone())
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let c = Decompressor::new(dictionary);
+        /* The pass 2 output is tiny, so only pass 1 can notice the limit */
+        let err = c
+            .decompress_to_bytes(&out, cert_bytes.len() as u32 - 1)
+            .expect_err("Shouldn't be enough space!");
+        assert!(matches!(err, Error::SizeLimitExceeded { .. }));
+        assert_eq!(c.decompress_to_bytes(&out, cert_bytes.len() as u32).unwrap(), cert_bytes);
+    }
+
+    #[test]
+    fn declared_length_is_exact() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let out = Compressor::new_from_builtin()
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let d = Decompressor::new_from_builtin();
+        let mut round_trip = vec![0; cert_bytes.len()];
+        d.decompress(&out, &mut round_trip).expect("Decompression succeeds");
+        assert_eq!(cert_bytes, round_trip);
+        /* The declared length must be exact, not just large enough */
+        let mut too_long = vec![0; cert_bytes.len() + 1];
+        assert!(d.decompress(&out, &mut too_long).is_err());
+    }
 }
 #[cfg(test)]
-#[cfg(all(feature ="nightly-features"))]
+#[cfg(feature = "nightly-features")]
 mod datatests {
 
     #[datatest::files("data/certificate_messages", {
@@ -2,23 +2,38 @@
 
 include!(concat!(env!("OUT_DIR"), "/builtin_tables.rs"));
 
-fn hash(bytes: &[u8]) -> [u8; 32] {
-    let mut result_256 = [0; 32];
-    blake::hash(256, bytes, &mut result_256).expect("Error hashing");
-    result_256
-}
-
 pub fn cert_to_identifier(cert: &[u8]) -> Option<&'static [u8]> {
-    let h = hash(cert);
-    HASH_TO_ID.get(&h).copied()
+    CERT_INDEX
+        .get(&super::index_key(cert)[..])?
+        .iter()
+        .copied()
+        .find(|id| ID_TO_CERT.get(*id) == Some(&cert))
 }
 
 pub fn id_to_cert(id: &[u8]) -> Option<&'static [u8]> {
     ID_TO_CERT.get(id).copied()
 }
 
-pub fn id_to_hash(id: &[u8]) -> Option<&'static [u8]> {
-    ID_TO_HASH.get(id).copied()
+/// The dictionary compiled into the crate from `data/pass1.json`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuiltinDictionary;
+
+impl super::CertDictionary for BuiltinDictionary {
+    fn cert_to_identifier(&self, cert: &[u8]) -> Option<&[u8]> {
+        cert_to_identifier(cert)
+    }
+
+    fn identifier_to_cert(&self, id: &[u8]) -> Option<&[u8]> {
+        id_to_cert(id)
+    }
+
+    fn metadata(&self) -> super::DictionaryMetadata {
+        super::DictionaryMetadata {
+            list_date: LIST_DATE.to_string(),
+            version: VERSION,
+            entries: ID_TO_CERT.len(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -28,26 +43,45 @@ mod tests {
     /* These tests check for bijectivity */
 
     #[test]
-    fn hashes_entries_agree() {
-        for (h, id) in HASH_TO_ID.entries() {
-            let cert = id_to_cert(*id).expect("Should be present");
-            assert_eq!(hash(&cert), *h);
+    fn index_entries_agree() {
+        for (key, ids) in CERT_INDEX.entries() {
+            for id in *ids {
+                let cert = id_to_cert(id).expect("Should be present");
+                assert_eq!(super::super::index_key(cert), *key);
+            }
         }
     }
 
     #[test]
     fn id_entries_agree() {
         for (id, cert) in ID_TO_CERT.entries() {
-            let lookup = cert_to_identifier(*cert).expect("Should be present");
+            if RETIRED.contains(id) {
+                assert_eq!(cert_to_identifier(cert), None);
+                continue;
+            }
+            let lookup = cert_to_identifier(cert).expect("Should be present");
             assert_eq!(*id, lookup);
         }
     }
 
     #[test]
-    fn  id_to_hash_agree() {
-        for (h, id) in HASH_TO_ID.entries() {
-            let h_stored = id_to_hash(*id).expect("Should be present");
-            assert_eq!(h_stored, *h);
+    fn index_hits_are_confirmed() {
+        /* Same length and suffix as a dictionary entry, but a different certificate */
+        let (_, cert) = ID_TO_CERT.entries().next().expect("Dictionary is not empty");
+        let mut forged = cert.to_vec();
+        forged[0] ^= 0xff;
+        assert_eq!(cert_to_identifier(&forged), None);
+    }
+
+    #[test]
+    fn dictionary_agrees_with_tables() {
+        use super::super::CertDictionary;
+        let dict = BuiltinDictionary;
+        assert_eq!(dict.metadata().entries, ID_TO_CERT.len());
+        for (id, cert) in ID_TO_CERT.entries() {
+            let expected = Some(*id).filter(|x| !RETIRED.contains(x));
+            assert_eq!(dict.cert_to_identifier(cert), expected);
+            assert_eq!(dict.identifier_to_cert(id), Some(*cert));
         }
     }
 }
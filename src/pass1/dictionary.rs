@@ -0,0 +1,601 @@
+use super::{index_key, CertDictionary, DictionaryMetadata, IndexKey};
+use crate::Error;
+use bytes::{Buf, BufMut};
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// Every identifier starts with this byte, followed by a big-endian `u16` counter.
+pub const IDENTIFIER_PREFIX: u8 = 0xff;
+pub const IDENTIFIER_LEN: usize = 3;
+
+const BINARY_MAGIC: &[u8; 4] = b"ACD2";
+
+/// A pass 1 dictionary loaded at runtime rather than compiled into the crate.
+///
+/// Dictionaries are append-only across versions: an identifier is never reassigned to a different
+/// certificate. Certificates which leave the trust store are retired instead. Retired entries are
+/// still expanded by the decompressor, but the compressor no longer produces them.
+#[derive(Debug, Clone)]
+pub struct LoadedDictionary {
+    list_date: String,
+    version: u32,
+    id_to_cert: HashMap<Vec<u8>, Vec<u8>>,
+    /* Identifiers by the index key of their certificate */
+    index: HashMap<IndexKey, Vec<Vec<u8>>>,
+    retired: BTreeSet<Vec<u8>>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct JsonDictionary {
+    #[serde(deserialize_with = "entries_in_order")]
+    data: Vec<(String, String)>,
+    list_date: String,
+    #[serde(default = "default_version")]
+    version: u32,
+    #[serde(default)]
+    retired: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonDictionaryRef<'a> {
+    data: BTreeMap<String, String>,
+    list_date: &'a str,
+    version: u32,
+    retired: Vec<String>,
+}
+
+/// The changes between two versions of a dictionary, as produced by [`LoadedDictionary::update`].
+/// Identifiers are hex encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DictionaryDiff {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Certificates new to this version, with freshly allocated identifiers.
+    pub added: Vec<String>,
+    /// Certificates no longer in the trust store.
+    pub retired: Vec<String>,
+    /// Previously retired certificates which are back in the trust store under their old identifier.
+    pub reinstated: Vec<String>,
+}
+
+impl DictionaryDiff {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Serializing strings cannot fail")
+    }
+}
+
+/// Hands out identifiers in the order certificates are added to a dictionary.
+#[derive(Debug, Clone)]
+pub struct IdentifierAllocator {
+    prefix: u8,
+    position: u32,
+}
+
+impl IdentifierAllocator {
+    pub fn new(prefix: u8) -> Self {
+        IdentifierAllocator { prefix, position: 0 }
+    }
+
+    /// Continues allocating from `position`, e.g. one past every identifier already in a
+    /// dictionary. Positions past `u16::MAX` give an allocator which is already exhausted.
+    pub fn starting_at(prefix: u8, position: u32) -> Self {
+        IdentifierAllocator { prefix, position }
+    }
+
+    /// Returns `None` once the `u16` counter is exhausted.
+    pub fn next_identifier(&mut self) -> Option<Vec<u8>> {
+        let position = u16::try_from(self.position).ok()?;
+        self.position += 1;
+        let mut id = vec![self.prefix];
+        id.extend_from_slice(&position.to_be_bytes());
+        Some(id)
+    }
+}
+
+/* serde_json silently keeps the last value for a repeated key, so collect the pairs ourselves */
+fn entries_in_order<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<(String, String)>, D::Error> {
+    struct EntryVisitor;
+
+    impl<'de> Visitor<'de> for EntryVisitor {
+        type Value = Vec<(String, String)>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a map from hex identifiers to hex certificates")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    d.deserialize_map(EntryVisitor)
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidDictionary(reason.into())
+}
+
+pub fn is_valid_identifier(id: &[u8]) -> bool {
+    id.len() == IDENTIFIER_LEN && id[0] == IDENTIFIER_PREFIX
+}
+
+impl LoadedDictionary {
+    /// Builds a dictionary from `(identifier, certificate)` pairs, checking that identifiers
+    /// are well formed and that neither identifiers nor certificates repeat.
+    pub fn from_entries(
+        list_date: String,
+        entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<Self, Error> {
+        Self::from_versioned_entries(list_date, 1, entries, Vec::new())
+    }
+
+    /// As [`Self::from_entries`], additionally marking the identifiers in `retired` as retired.
+    pub fn from_versioned_entries(
+        list_date: String,
+        version: u32,
+        entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+        retired: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<Self, Error> {
+        if list_date.len() > u8::MAX as usize {
+            return Err(invalid("List date too long"));
+        }
+        let mut id_to_cert = HashMap::new();
+        let mut index: HashMap<IndexKey, Vec<Vec<u8>>> = HashMap::new();
+        for (id, cert) in entries {
+            if !is_valid_identifier(&id) {
+                return Err(invalid(format!(
+                    "Malformed identifier {}",
+                    hex::encode(&id)
+                )));
+            }
+            if cert.is_empty() || cert.len() >= 1 << 24 {
+                return Err(invalid(format!(
+                    "Invalid certificate length for identifier {}",
+                    hex::encode(&id)
+                )));
+            }
+            if id_to_cert.contains_key(&id) {
+                return Err(invalid(format!(
+                    "Duplicate identifier {}",
+                    hex::encode(&id)
+                )));
+            }
+            if let Some(other) = find_identifier(&index, &id_to_cert, &cert) {
+                return Err(invalid(format!(
+                    "Identifiers {} and {} map to the same certificate",
+                    hex::encode(other),
+                    hex::encode(&id)
+                )));
+            }
+            index.entry(index_key(&cert)).or_default().push(id.clone());
+            id_to_cert.insert(id, cert);
+        }
+        let mut retired_ids = BTreeSet::new();
+        for id in retired {
+            if !id_to_cert.contains_key(&id) || !retired_ids.insert(id.clone()) {
+                return Err(invalid(format!(
+                    "Retired identifier {} is unknown or repeated",
+                    hex::encode(&id)
+                )));
+            }
+        }
+        Ok(LoadedDictionary {
+            list_date,
+            version,
+            id_to_cert,
+            index,
+            retired: retired_ids,
+        })
+    }
+
+    /// Produces the next version of this dictionary for the trust store `certs`, given in the
+    /// order new identifiers should be allocated.
+    ///
+    /// Every existing identifier keeps its certificate. Certificates not already present are
+    /// appended with fresh identifiers, and present ones missing from `certs` are retired.
+    pub fn update(
+        &self,
+        list_date: String,
+        certs: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<(LoadedDictionary, DictionaryDiff), Error> {
+        let next_position = self
+            .id_to_cert
+            .keys()
+            .map(|id| u32::from(u16::from_be_bytes([id[1], id[2]])) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut allocator = IdentifierAllocator::starting_at(IDENTIFIER_PREFIX, next_position);
+        let mut diff = DictionaryDiff {
+            from_version: self.version,
+            to_version: self.version + 1,
+            ..Default::default()
+        };
+
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = self
+            .entries()
+            .map(|(id, cert)| (id.to_vec(), cert.to_vec()))
+            .collect();
+        let mut present = BTreeSet::new();
+        let mut added: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        for cert in certs {
+            let existing = find_identifier(&self.index, &self.id_to_cert, &cert);
+            if let Some(id) = existing.or_else(|| added.get(&cert)) {
+                if present.insert(id.clone()) && self.retired.contains(id) {
+                    diff.reinstated.push(hex::encode(id));
+                }
+                continue;
+            }
+            let id = allocator
+                .next_identifier()
+                .ok_or(Error::IdentifiersExhausted)?;
+            diff.added.push(hex::encode(&id));
+            present.insert(id.clone());
+            added.insert(cert.clone(), id.clone());
+            entries.push((id, cert));
+        }
+        let retired: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| !present.contains(*id))
+            .cloned()
+            .collect();
+        diff.retired = retired
+            .iter()
+            .filter(|id| !self.retired.contains(*id))
+            .map(hex::encode)
+            .collect();
+        let updated = Self::from_versioned_entries(list_date, diff.to_version, entries, retired)?;
+        Ok((updated, diff))
+    }
+
+    /// Parses the `{"data": {id_hex: cert_hex}, "list_date": ...}` format used for `data/pass1.json`.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let table: JsonDictionary = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        let mut entries = Vec::with_capacity(table.data.len());
+        for (id, cert) in table.data {
+            let id = hex::decode(&id)
+                .map_err(|_| invalid(format!("Identifier {} is not hex", id)))?;
+            let cert = hex::decode(cert).map_err(|_| {
+                invalid(format!("Certificate for {} is not hex", hex::encode(&id)))
+            })?;
+            entries.push((id, cert));
+        }
+        let mut retired = Vec::with_capacity(table.retired.len());
+        for id in table.retired {
+            retired.push(
+                hex::decode(&id)
+                    .map_err(|_| invalid(format!("Identifier {} is not hex", id)))?,
+            );
+        }
+        Self::from_versioned_entries(table.list_date, table.version, entries, retired)
+    }
+
+    pub fn to_json(&self) -> String {
+        let table = JsonDictionaryRef {
+            data: self
+                .entries()
+                .map(|(id, cert)| (hex::encode(id), hex::encode(cert)))
+                .collect(),
+            list_date: &self.list_date,
+            version: self.version,
+            retired: self.retired.iter().map(hex::encode).collect(),
+        };
+        serde_json::to_string_pretty(&table).expect("Serializing strings cannot fail")
+    }
+
+    // Binary format:
+    //     opaque magic[4] = "ACD2";
+    //     opaque list_date<0..2^8-1>;
+    //     uint32 version;
+    //     uint32 entry_count;
+    //     struct {
+    //         opaque identifier[3];
+    //         opaque cert_data<1..2^24-1>;
+    //     } entries[entry_count];
+    //     uint32 retired_count;
+    //     opaque retired_identifiers[3][retired_count];
+    pub fn from_binary(mut bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < BINARY_MAGIC.len() || &bytes[..BINARY_MAGIC.len()] != BINARY_MAGIC {
+            return Err(invalid("Missing binary dictionary header"));
+        }
+        bytes.advance(BINARY_MAGIC.len());
+        let list_date = read_vec::<1>(&mut bytes)?;
+        let list_date =
+            String::from_utf8(list_date.to_vec()).map_err(|_| invalid("List date is not UTF-8"))?;
+        if bytes.remaining() < 8 {
+            return Err(invalid("Truncated entry count"));
+        }
+        let version = bytes.get_u32();
+        let count = bytes.get_u32();
+        let mut entries = Vec::with_capacity(count.min(u16::MAX as u32) as usize);
+        for _ in 0..count {
+            if bytes.remaining() < IDENTIFIER_LEN {
+                return Err(invalid("Truncated identifier"));
+            }
+            let id = bytes[..IDENTIFIER_LEN].to_vec();
+            bytes.advance(IDENTIFIER_LEN);
+            let cert = read_vec::<3>(&mut bytes)?.to_vec();
+            entries.push((id, cert));
+        }
+        if bytes.remaining() < 4 {
+            return Err(invalid("Truncated retired count"));
+        }
+        let count = bytes.get_u32() as usize;
+        if bytes.remaining() != count.saturating_mul(IDENTIFIER_LEN) {
+            return Err(invalid("Retired identifiers do not match their count"));
+        }
+        let retired: Vec<Vec<u8>> = bytes.chunks(IDENTIFIER_LEN).map(<[u8]>::to_vec).collect();
+        Self::from_versioned_entries(list_date, version, entries, retired)
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.put_slice(BINARY_MAGIC);
+        output.put_u8(self.list_date.len() as u8);
+        output.put_slice(self.list_date.as_bytes());
+        output.put_u32(self.version);
+        output.put_u32(self.id_to_cert.len() as u32);
+        for (id, cert) in self.entries() {
+            output.put_slice(id);
+            output.put_uint(cert.len() as u64, 3);
+            output.put_slice(cert);
+        }
+        output.put_u32(self.retired.len() as u32);
+        for id in &self.retired {
+            output.put_slice(id);
+        }
+        output
+    }
+
+    /// Loads a dictionary from disk, accepting either the JSON or the binary format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let contents = std::fs::read(path)?;
+        if contents.starts_with(BINARY_MAGIC) {
+            Self::from_binary(&contents)
+        } else {
+            Self::from_json(std::str::from_utf8(&contents).map_err(|_| invalid("Dictionary is not UTF-8"))?)
+        }
+    }
+
+    /// Iterates over `(identifier, certificate)` pairs in identifier order.
+    pub fn entries(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let sorted: BTreeMap<_, _> = self.id_to_cert.iter().collect();
+        sorted.into_iter().map(|(id, cert)| (id.as_slice(), cert.as_slice()))
+    }
+
+    pub fn list_date(&self) -> &str {
+        &self.list_date
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn is_retired(&self, id: &[u8]) -> bool {
+        self.retired.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.id_to_cert.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.id_to_cert.is_empty()
+    }
+}
+
+fn read_vec<'a, const WIDTH: usize>(bytes: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    if bytes.remaining() < WIDTH {
+        return Err(invalid("Not enough bytes to read length field"));
+    }
+    let len = bytes.get_uint(WIDTH) as usize;
+    if bytes.remaining() < len {
+        return Err(invalid("Length field longer than remaining bytes"));
+    }
+    let (vec, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(vec)
+}
+
+fn find_identifier<'a>(
+    index: &'a HashMap<IndexKey, Vec<Vec<u8>>>,
+    id_to_cert: &HashMap<Vec<u8>, Vec<u8>>,
+    cert: &[u8],
+) -> Option<&'a Vec<u8>> {
+    index
+        .get(&index_key(cert))?
+        .iter()
+        .find(|id| id_to_cert.get(*id).is_some_and(|x| x == cert))
+}
+
+impl CertDictionary for LoadedDictionary {
+    fn cert_to_identifier(&self, cert: &[u8]) -> Option<&[u8]> {
+        find_identifier(&self.index, &self.id_to_cert, cert)
+            .filter(|id| !self.retired.contains(*id))
+            .map(Vec::as_slice)
+    }
+
+    fn identifier_to_cert(&self, id: &[u8]) -> Option<&[u8]> {
+        self.id_to_cert.get(id).map(Vec::as_slice)
+    }
+
+    fn metadata(&self) -> DictionaryMetadata {
+        DictionaryMetadata {
+            list_date: self.list_date.clone(),
+            version: self.version,
+            entries: self.id_to_cert.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> LoadedDictionary {
+        LoadedDictionary::from_entries(
+            "2024-01-01".to_string(),
+            vec![
+                (vec![0xff, 0x00, 0x00], vec![0x30, 0x01, 0x00]),
+                (vec![0xff, 0x00, 0x01], vec![0x30, 0x01, 0x01]),
+            ],
+        )
+        .expect("Valid dictionary")
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dict = sample();
+        let parsed = LoadedDictionary::from_json(&dict.to_json()).expect("Parses");
+        assert_eq!(parsed.list_date(), "2024-01-01");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(
+            parsed.identifier_to_cert(&[0xff, 0x00, 0x01]),
+            Some(&[0x30, 0x01, 0x01][..])
+        );
+        assert_eq!(
+            parsed.cert_to_identifier(&[0x30, 0x01, 0x00]),
+            Some(&[0xff, 0x00, 0x00][..])
+        );
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let dict = sample();
+        let binary = dict.to_binary();
+        let parsed = LoadedDictionary::from_binary(&binary).expect("Parses");
+        assert_eq!(parsed.metadata(), dict.metadata());
+        assert_eq!(parsed.to_binary(), binary);
+        assert!(LoadedDictionary::from_binary(&binary[..binary.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn update_is_append_only() {
+        let dict = sample();
+        let certs = vec![vec![0x30, 0x01, 0x01], vec![0x30, 0x01, 0x02], vec![0x30, 0x01, 0x03]];
+        let (updated, diff) = dict.update("2024-02-01".to_string(), certs).expect("Updates");
+        assert_eq!(updated.version(), 2);
+        assert_eq!(updated.len(), 4);
+        assert_eq!(diff.added, ["ff0002", "ff0003"]);
+        assert_eq!(diff.retired, ["ff0000"]);
+        assert!(diff.reinstated.is_empty());
+        for (id, cert) in dict.entries() {
+            assert_eq!(updated.identifier_to_cert(id), Some(cert));
+        }
+        /* Retired certificates still decompress but are no longer compressed */
+        assert!(updated.is_retired(&[0xff, 0x00, 0x00]));
+        assert_eq!(updated.cert_to_identifier(&[0x30, 0x01, 0x00]), None);
+        assert_eq!(
+            updated.identifier_to_cert(&[0xff, 0x00, 0x00]),
+            Some(&[0x30, 0x01, 0x00][..])
+        );
+
+        let certs = vec![vec![0x30, 0x01, 0x00], vec![0x30, 0x01, 0x02]];
+        let (third, diff) = updated.update("2024-03-01".to_string(), certs).unwrap();
+        assert_eq!(third.version(), 3);
+        assert_eq!(third.len(), 4);
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.retired, ["ff0001", "ff0003"]);
+        assert_eq!(diff.reinstated, ["ff0000"]);
+        assert_eq!(
+            third.cert_to_identifier(&[0x30, 0x01, 0x00]),
+            Some(&[0xff, 0x00, 0x00][..])
+        );
+        let parsed: DictionaryDiff = serde_json::from_str(&diff.to_json()).unwrap();
+        assert_eq!(parsed, diff);
+    }
+
+    #[test]
+    fn update_full_dictionary() {
+        let dict = LoadedDictionary::from_entries(
+            "2024-01-01".to_string(),
+            vec![(vec![0xff, 0xff, 0xff], vec![0x30, 0x01, 0x00])],
+        )
+        .unwrap();
+        /* Only a certificate which needs a new identifier fails */
+        let (updated, diff) = dict
+            .update("2024-02-01".to_string(), vec![vec![0x30, 0x01, 0x00]])
+            .expect("Nothing to allocate");
+        assert_eq!(updated.len(), 1);
+        assert!(diff.added.is_empty());
+        let err = dict
+            .update("2024-02-01".to_string(), vec![vec![0x30, 0x01, 0x01]])
+            .expect_err("No identifiers left");
+        assert!(matches!(err, Error::IdentifiersExhausted), "{:?}", err);
+    }
+
+    #[test]
+    fn versions_survive_serialization() {
+        let (dict, _) = sample()
+            .update("2024-02-01".to_string(), vec![vec![0x30, 0x01, 0x02]])
+            .unwrap();
+        for parsed in [
+            LoadedDictionary::from_json(&dict.to_json()).unwrap(),
+            LoadedDictionary::from_binary(&dict.to_binary()).unwrap(),
+        ] {
+            assert_eq!(parsed.metadata(), dict.metadata());
+            assert!(parsed.is_retired(&[0xff, 0x00, 0x01]));
+            assert!(!parsed.is_retired(&[0xff, 0x00, 0x02]));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_retired_identifiers() {
+        let json = r#"{"data": {"ff0000": "3001"}, "list_date": "2024-01-01", "retired": ["ff0001"]}"#;
+        assert!(LoadedDictionary::from_json(json).is_err());
+    }
+
+    #[test]
+    fn index_collisions_are_resolved() {
+        /* Equal lengths and suffixes share an index bucket */
+        let first = vec![0x30, 0xaa, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let second = vec![0x30, 0xbb, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let dict = LoadedDictionary::from_entries(
+            "2024-01-01".to_string(),
+            vec![(vec![0xff, 0x00, 0x00], first.clone()), (vec![0xff, 0x00, 0x01], second.clone())],
+        )
+        .unwrap();
+        assert_eq!(dict.cert_to_identifier(&first), Some(&[0xff, 0x00, 0x00][..]));
+        assert_eq!(dict.cert_to_identifier(&second), Some(&[0xff, 0x00, 0x01][..]));
+        let mut third = first.clone();
+        third[1] = 0xcc;
+        assert_eq!(dict.cert_to_identifier(&third), None);
+    }
+
+    #[test]
+    fn rejects_duplicate_identifiers() {
+        let json = r#"{"data": {"ff0000": "3001", "ff0000": "3002"}, "list_date": "2024-01-01"}"#;
+        assert!(LoadedDictionary::from_json(json).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_certs() {
+        let json = r#"{"data": {"ff0000": "3001", "ff0001": "3001"}, "list_date": "2024-01-01"}"#;
+        assert!(LoadedDictionary::from_json(json).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_identifiers() {
+        for id in ["ff00", "fe0000", "ff000000", "zz0000"] {
+            let json = format!(
+                r#"{{"data": {{"{}": "3001"}}, "list_date": "2024-01-01"}}"#,
+                id
+            );
+            assert!(
+                LoadedDictionary::from_json(&json).is_err(),
+                "{} accepted",
+                id
+            );
+        }
+    }
+}
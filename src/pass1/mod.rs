@@ -1,121 +1,258 @@
-use super::tls::{CertificateEntry, CertificateMessage};
-use bytes::{BufMut, Bytes};
+use super::tls::CertificateMessage;
+use super::Error;
 use std::io::Write;
+use std::sync::Arc;
 
 mod builtins;
+mod dictionary;
+pub mod ocsp;
 
-pub type IdFunc = fn(&[u8]) -> Option<&'static [u8]>;
-pub type CertFunc = fn(&[u8]) -> Option<&'static [u8]>;
+pub use builtins::BuiltinDictionary;
+pub use dictionary::{
+    is_valid_identifier, DictionaryDiff, IdentifierAllocator, LoadedDictionary, IDENTIFIER_LEN,
+    IDENTIFIER_PREFIX,
+};
+
+/* Certificates end with a signature, so their length and last few bytes almost always single
+them out. The index only narrows the search: a hit is confirmed by comparing the whole certificate. */
+const INDEX_SUFFIX_LEN: usize = 8;
+type IndexKey = [u8; 3 + INDEX_SUFFIX_LEN];
+
+fn index_key(cert: &[u8]) -> IndexKey {
+    let mut key = [0; 3 + INDEX_SUFFIX_LEN];
+    key[..3].copy_from_slice(&(cert.len() as u32).to_be_bytes()[1..]);
+    let suffix = &cert[cert.len().saturating_sub(INDEX_SUFFIX_LEN)..];
+    key[3 + INDEX_SUFFIX_LEN - suffix.len()..].copy_from_slice(suffix);
+    key
+}
+
+/// Describes the contents of a pass 1 dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryMetadata {
+    /// The date of the CCADB listing the dictionary was built from.
+    pub list_date: String,
+    /// Incremented each time the dictionary is updated.
+    pub version: u32,
+    /// The number of certificates in the dictionary.
+    pub entries: usize,
+}
+
+/// A bidirectional mapping between certificates and the identifiers which replace them in pass 1.
+///
+/// Implementations must be consistent: if `cert_to_identifier(c)` returns `id` then
+/// `identifier_to_cert(id)` must return `c`.
+pub trait CertDictionary: std::fmt::Debug + Send + Sync {
+    fn cert_to_identifier(&self, cert: &[u8]) -> Option<&[u8]>;
+    fn identifier_to_cert(&self, id: &[u8]) -> Option<&[u8]>;
+    fn metadata(&self) -> DictionaryMetadata;
+}
+
+/* Replacement extension blocks for entries whose OCSP staples were rewritten, by entry index */
+type Staples = Vec<Option<Vec<u8>>>;
 
 #[derive(Debug)]
 pub struct Compressor {
-    lookup: IdFunc,
+    dictionary: Arc<dyn CertDictionary>,
+    ocsp_staples: bool,
+    strict: bool,
 }
 
 impl Compressor {
-    pub fn new(lookup: IdFunc) -> Self {
-        Compressor { lookup }
+    pub fn new(dictionary: Arc<dyn CertDictionary>) -> Self {
+        Compressor {
+            dictionary,
+            ocsp_staples: false,
+            strict: false,
+        }
     }
 
     pub fn new_builtin() -> Self {
-        Compressor {
-            lookup: builtins::cert_to_identifier,
-        }
+        Self::new(Arc::new(BuiltinDictionary))
     }
 
-    fn map_or_preserve_cert_entry(&self, mut entry: CertificateEntry) -> CertificateEntry {
-        let cert = &entry.data;
-        /* Debug builds validate that the user supplied function is correct */
-        debug_assert_eq!(
-            (self.lookup)(cert),
-            self::builtins::cert_to_identifier(cert)
-        );
-        entry.data = (self.lookup)(cert).map_or(entry.data, Bytes::from_static);
-        entry
+    /// Also abridges stapled OCSP responses, see [`ocsp`]. The `Decompressor` must match.
+    pub fn with_ocsp_staples(mut self, enabled: bool) -> Self {
+        self.ocsp_staples = enabled;
+        self
+    }
+
+    pub fn ocsp_staples(&self) -> bool {
+        self.ocsp_staples
+    }
+
+    /// Fails with [`Error::AmbiguousLiteral`] rather than send a certificate entry which is
+    /// itself a valid identifier, since it would not decompress to the same message.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn metadata(&self) -> DictionaryMetadata {
+        self.dictionary.metadata()
+    }
+
+    /* Entries point into either the input, the dictionary or `staples`, so nothing else is copied
+    until the output is written */
+    fn abridge<'a>(
+        &'a self,
+        cert_msg: &'a [u8],
+        staples: &'a mut Staples,
+    ) -> Result<CertificateMessage<'a>, Error> {
+        let mut cert_msg = CertificateMessage::read_from_bytes(cert_msg)?;
+        if self.ocsp_staples {
+            *staples = ocsp::abridge_staples(&cert_msg, &*self.dictionary)?;
+        }
+        let staples = &*staples;
+        for (index, entry) in cert_msg.certificate_entries.iter_mut().enumerate() {
+            if let Some(Some(extensions)) = staples.get(index) {
+                entry.extensions = extensions;
+            }
+            if let Some(id) = self.dictionary.cert_to_identifier(entry.data) {
+                entry.data = id;
+            } else if self.strict && is_valid_identifier(entry.data) {
+                return Err(Error::AmbiguousLiteral { entry: index });
+            }
+        }
+        Ok(cert_msg)
     }
 
     pub fn compress_to_bytes(
         &self,
         cert_msg: &[u8],
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let output = Vec::with_capacity(cert_msg.len());
-        let mut writer = output.writer();
-        self.compress(cert_msg, &mut writer)?;
-        Ok(writer.into_inner())
+    ) -> Result<Vec<u8>, Error> {
+        self.abridge(cert_msg, &mut Vec::new())?.to_vec()
     }
 
     pub fn compress(
         &self,
         cert_msg: &[u8],
         writer: &mut impl Write,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        /* TODO: Why do we need ownership here? It would be nice just to reference the slice */
-        let mut cert_msg = Bytes::copy_from_slice(cert_msg);
-        let mut cert_msg = CertificateMessage::read_from_bytes(&mut cert_msg)?;
-        cert_msg.certificate_entries = cert_msg
-            .certificate_entries
-            .into_iter()
-            .map(|x| self.map_or_preserve_cert_entry(x))
-            .collect();
-        cert_msg.write_to_bytes(writer)?;
-        Ok(())
+    ) -> Result<(), Error> {
+        self.abridge(cert_msg, &mut Vec::new())?.write_to_bytes(writer)
     }
 }
 
-
-
 #[derive(Debug)]
 pub struct Decompressor {
-    lookup: CertFunc,
+    dictionary: Arc<dyn CertDictionary>,
+    ocsp_staples: bool,
+    strict: bool,
 }
 
 impl Decompressor {
-    pub fn new(lookup: CertFunc) -> Self {
-        Decompressor { lookup }
+    pub fn new(dictionary: Arc<dyn CertDictionary>) -> Self {
+        Decompressor {
+            dictionary,
+            ocsp_staples: false,
+            strict: false,
+        }
     }
 
     pub fn new_builtin() -> Self {
-        Decompressor {
-            lookup: builtins::id_to_cert,
-        }
+        Self::new(Arc::new(BuiltinDictionary))
     }
 
-    fn map_identifier(&self, mut entry: CertificateEntry) -> CertificateEntry {
-        let id_or_cert = &entry.data;
-        /* Debug builds validate that the user supplied function is correct */
-        debug_assert_eq!(
-            (self.lookup)(id_or_cert),
-            self::builtins::id_to_cert(id_or_cert)
-        );
-        entry.data = (self.lookup)(id_or_cert).map_or(entry.data, Bytes::from_static);
-        entry
+    /// Also expands stapled OCSP responses abridged by a `Compressor` configured the same way.
+    pub fn with_ocsp_staples(mut self, enabled: bool) -> Self {
+        self.ocsp_staples = enabled;
+        self
+    }
+
+    pub fn ocsp_staples(&self) -> bool {
+        self.ocsp_staples
+    }
+
+    /// Rejects messages which a strict `Compressor` would not have produced, with
+    /// [`Error::NonCanonical`] or [`Error::UnknownIdentifier`], so that each accepted message has
+    /// exactly one encoding.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn metadata(&self) -> DictionaryMetadata {
+        self.dictionary.metadata()
+    }
+
+    /* Replaces identifiers with their certificates, failing as soon as the decompressed message
+    would be larger than `max_size`. Only OCSP staples are copied, so the check happens before
+    any other allocation. */
+    fn expand<'a>(
+        &'a self,
+        compressed_msg: &'a [u8],
+        max_size: usize,
+        staples: &'a mut Staples,
+    ) -> Result<CertificateMessage<'a>, Error> {
+        let mut cert_msg = CertificateMessage::read_from_bytes(compressed_msg)?;
+        if self.ocsp_staples {
+            *staples = ocsp::expand_staples(&cert_msg, &*self.dictionary, max_size, self.strict)?;
+        }
+        let staples = &*staples;
+        let mut size = 1 + cert_msg.request_context.len() + 3;
+        let mut offset = size;
+        for (index, entry) in cert_msg.certificate_entries.iter_mut().enumerate() {
+            let cert = self.dictionary.identifier_to_cert(entry.data);
+            /* A lenient compressor sends such entries as they are, so only a strict one can't have */
+            if self.strict && cert.is_none() && is_valid_identifier(entry.data) {
+                return Err(Error::UnknownIdentifier {
+                    identifier: entry.data.to_vec(),
+                    offset,
+                    entry: index,
+                });
+            }
+            if self.strict && cert.is_none() && self.dictionary.cert_to_identifier(entry.data).is_some() {
+                return Err(Error::NonCanonical { offset, entry: index });
+            }
+            offset += entry.encoded_len();
+            if let Some(Some(extensions)) = staples.get(index) {
+                entry.extensions = extensions;
+            }
+            let data_len = cert.map_or(entry.data.len(), <[u8]>::len);
+            size += 3 + data_len + 2 + entry.extensions.len();
+            if size > max_size {
+                return Err(Error::SizeLimitExceeded { limit: max_size });
+            }
+            if let Some(cert) = cert {
+                entry.data = cert;
+            }
+        }
+        if size > max_size {
+            return Err(Error::SizeLimitExceeded { limit: max_size });
+        }
+        Ok(cert_msg)
     }
 
     pub fn decompress_to_bytes(
         &self,
         compressed_msg: &[u8],
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let output = Vec::with_capacity(compressed_msg.len());
-        let mut writer = output.writer();
-        self.decompress(compressed_msg, &mut writer)?;
-        Ok(writer.into_inner())
+    ) -> Result<Vec<u8>, Error> {
+        self.decompress_to_bytes_with_limit(compressed_msg, usize::MAX)
+    }
+
+    /// Fails with [`Error::SizeLimitExceeded`] if the decompressed message would be larger than `max_size`.
+    pub fn decompress_to_bytes_with_limit(
+        &self,
+        compressed_msg: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        self.expand(compressed_msg, max_size, &mut Vec::new())?.to_vec()
     }
 
-    /* TODO: This needs to support a maximum size */
     pub fn decompress(
         &self,
         compressed_msg: &[u8],
         writer: &mut impl Write,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut compressed_msg = Bytes::copy_from_slice(compressed_msg);
-        let mut cert_msg = CertificateMessage::read_from_bytes(&mut compressed_msg)?;
-        cert_msg.certificate_entries = cert_msg
-            .certificate_entries
-            .into_iter()
-            .map(|x| self.map_identifier(x))
-            .collect();
-        cert_msg.write_to_bytes(writer)?;
-        Ok(())
+    ) -> Result<(), Error> {
+        self.decompress_with_limit(compressed_msg, usize::MAX, writer)
+    }
+
+    pub fn decompress_with_limit(
+        &self,
+        compressed_msg: &[u8],
+        max_size: usize,
+        writer: &mut impl Write,
+    ) -> Result<(), Error> {
+        self.expand(compressed_msg, max_size, &mut Vec::new())?.write_to_bytes(writer)
     }
 }
 
@@ -185,10 +322,137 @@ mod tests {
         let round_trip = c.decompress_to_bytes(&out).expect("Compression succeeds");
         assert_eq!(cert_bytes, round_trip);
     }
+
+    #[test]
+    fn round_trip_loaded_dictionary() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        /* Skip the context and list lengths, then the certificate's length and trailing extensions */
+        let cert = cert_bytes[7..cert_bytes.len() - 2].to_vec();
+        let dictionary: Arc<dyn CertDictionary> = Arc::new(
+            LoadedDictionary::from_entries("2024-01-01".into(), vec![(vec![0xff, 0x12, 0x34], cert)])
+                .expect("Valid dictionary"),
+        );
+        let c = Compressor::new(dictionary.clone());
+        let out = c
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        assert_eq!(out, [0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0xff, 0x12, 0x34, 0x00, 0x00]);
+        let c = Decompressor::new(dictionary);
+        let round_trip = c.decompress_to_bytes(&out).expect("Decompression succeeds");
+        assert_eq!(cert_bytes, round_trip);
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let cert = cert_bytes[7..cert_bytes.len() - 2].to_vec();
+        let dictionary = LoadedDictionary::from_entries("2024-01-01".into(), vec![(vec![0xff, 0x12, 0x34], cert)])
+            .expect("Valid dictionary");
+        let c = Decompressor::new(Arc::new(dictionary));
+        let compressed = [0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0xff, 0x12, 0x34, 0x00, 0x00];
+        let round_trip = c
+            .decompress_to_bytes_with_limit(&compressed, cert_bytes.len())
+            .expect("Exactly enough space");
+        assert_eq!(cert_bytes, round_trip);
+        let err = c
+            .decompress_to_bytes_with_limit(&compressed, cert_bytes.len() - 1)
+            .expect_err("Shouldn't be enough space!");
+        assert!(matches!(err, Error::SizeLimitExceeded { limit } if limit == cert_bytes.len() - 1));
+    }
+
+    #[test]
+    fn request_contexts() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let cert = cert_bytes[7..cert_bytes.len() - 2].to_vec();
+        let dictionary: Arc<dyn CertDictionary> = Arc::new(
+            LoadedDictionary::from_entries("2024-01-01".into(), vec![(vec![0xff, 0x12, 0x34], cert)])
+                .expect("Valid dictionary"),
+        );
+        let mut with_context = vec![0x04, 0xde, 0xad, 0xbe, 0xef];
+        with_context.extend_from_slice(&cert_bytes[1..]);
+
+        let out = Compressor::new(dictionary.clone())
+            .compress_to_bytes(&with_context)
+            .expect("Compression succeeds");
+        assert_eq!(
+            out,
+            [0x04, 0xde, 0xad, 0xbe, 0xef, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0xff, 0x12, 0x34, 0x00, 0x00]
+        );
+        let c = Decompressor::new(dictionary);
+        /* The context counts towards the size limit */
+        let round_trip = c
+            .decompress_to_bytes_with_limit(&out, with_context.len())
+            .expect("Exactly enough space");
+        assert_eq!(with_context, round_trip);
+        let err = c
+            .decompress_to_bytes_with_limit(&out, with_context.len() - 1)
+            .expect_err("Shouldn't be enough space!");
+        assert!(matches!(err, Error::SizeLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn strict_encoding() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let cert = cert_bytes[7..cert_bytes.len() - 2].to_vec();
+        let dictionary: Arc<dyn CertDictionary> = Arc::new(
+            LoadedDictionary::from_entries("2024-01-01".into(), vec![(vec![0xff, 0x12, 0x34], cert)])
+                .expect("Valid dictionary"),
+        );
+        let c = Compressor::new(dictionary.clone()).with_strict(true);
+        let d = Decompressor::new(dictionary.clone()).with_strict(true);
+        let out = c.compress_to_bytes(&cert_bytes).expect("Compression succeeds");
+        assert_eq!(d.decompress_to_bytes(&out).expect("Canonical"), cert_bytes);
+
+        /* A literal entry holding an identifier would come back as the certificate */
+        let literal = [0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0xff, 0x12, 0x34, 0x00, 0x00];
+        let lenient = Compressor::new(dictionary.clone()).compress_to_bytes(&literal).unwrap();
+        assert_eq!(Decompressor::new(dictionary.clone()).decompress_to_bytes(&lenient).unwrap(), cert_bytes);
+        let err = c.compress_to_bytes(&literal).expect_err("Ambiguous");
+        assert!(matches!(err, Error::AmbiguousLiteral { entry: 0 }), "{:?}", err);
+
+        /* Dictionary certificates must be sent as identifiers */
+        let err = d.decompress_to_bytes(&cert_bytes).expect_err("Non-canonical");
+        assert!(matches!(err, Error::NonCanonical { offset: 4, entry: 0 }), "{:?}", err);
+        assert_eq!(Decompressor::new(dictionary).decompress_to_bytes(&cert_bytes).unwrap(), cert_bytes);
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        let dictionary: Arc<dyn CertDictionary> =
+            Arc::new(LoadedDictionary::from_entries("2024-01-01".into(), vec![]).unwrap());
+        /* A literal entry which looks like an identifier the dictionary lacks survives a lenient round trip */
+        let literal = [0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0xff, 0xab, 0xcd, 0x00, 0x00];
+        let compressed = Compressor::new(dictionary.clone())
+            .compress_to_bytes(&literal)
+            .expect("Compression succeeds");
+        assert_eq!(compressed, literal);
+        let round_trip = Decompressor::new(dictionary.clone())
+            .decompress_to_bytes(&compressed)
+            .expect("Decompression succeeds");
+        assert_eq!(round_trip, literal);
+
+        let err = Decompressor::new(dictionary)
+            .with_strict(true)
+            .decompress_to_bytes(&compressed)
+            .expect_err("Identifier is not in the dictionary");
+        assert!(
+            matches!(err, Error::UnknownIdentifier { ref identifier, offset: 4, entry: 0 } if identifier == &[0xff, 0xab, 0xcd]),
+            "{:?}",
+            err
+        );
+    }
 }
 
 #[cfg(test)]
-#[cfg(all(feature ="nightly-features"))]
+#[cfg(feature = "nightly-features")]
 mod datatests {
 
     #[datatest::files("data/certificate_messages", {
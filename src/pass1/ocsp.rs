@@ -0,0 +1,544 @@
+//! Abridges stapled OCSP responses with the pass 1 dictionary.
+//!
+//! An OCSP response identifies the CA in each CertID by hashes of its name and key, names its
+//! responder by name or key hash, and often embeds a delegated responder certificate issued by
+//! the same CA. When that CA is in the dictionary, all of these can be rebuilt from its
+//! identifier. The CAs considered are the dictionary certificates in the message and in the
+//! response itself.
+//!
+//! An abridged `status_request` extension replaces the OCSP status type with
+//! [`ABRIDGED_STATUS_TYPE`] and the response with a list of operations, which reproduce the
+//! response exactly when their outputs are concatenated:
+//!
+//! ```text
+//! literal:   0x00 | length[2] | bytes
+//! reference: field | identifier[3]
+//! ```
+//!
+//! `field` selects a value derived from the certificate with that identifier, see [`Field`].
+
+use super::{CertDictionary, IDENTIFIER_LEN};
+use crate::der::{children, read_element};
+use crate::tls::{encode_extensions, CertificateMessage, Extension, ExtensionValue, STATUS_REQUEST};
+use crate::Error;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Marks an abridged OCSP response. RFC 6066 only defines status type 1, OCSP.
+pub const ABRIDGED_STATUS_TYPE: u8 = 0xff;
+const LITERAL: u8 = 0;
+/* A reference is 4 bytes and splits a literal in two, so shorter matches make the output larger */
+const MIN_MATCH_LEN: usize = 8;
+
+/// The values a reference can stand for, each derived from a dictionary certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Field {
+    /// The whole certificate, e.g. an embedded responder certificate.
+    Certificate = 1,
+    /// The DER subject Name, e.g. a ResponderID or the issuer of a responder certificate.
+    Subject = 2,
+    /// The SHA-1 hash of the subject Name, as in a CertID.
+    Sha1Subject = 3,
+    /// The SHA-1 hash of the subjectPublicKey, as in a CertID, a ResponderID or a key identifier.
+    Sha1Key = 4,
+    Sha256Subject = 5,
+    Sha256Key = 6,
+}
+
+const FIELDS: [Field; 6] = [
+    Field::Certificate,
+    Field::Subject,
+    Field::Sha1Subject,
+    Field::Sha1Key,
+    Field::Sha256Subject,
+    Field::Sha256Key,
+];
+
+impl Field {
+    fn from_u8(value: u8) -> Option<Field> {
+        FIELDS.into_iter().find(|field| *field as u8 == value)
+    }
+
+    /// The value of this field for `cert`, or `None` if `cert` does not parse far enough.
+    pub fn value(self, cert: &[u8]) -> Option<Vec<u8>> {
+        if self == Field::Certificate {
+            return Some(cert.to_vec());
+        }
+        let (subject, key) = subject_and_key(cert)?;
+        Some(match self {
+            Field::Certificate => unreachable!(),
+            Field::Subject => cert[subject].to_vec(),
+            Field::Sha1Subject => Sha1::digest(&cert[subject]).to_vec(),
+            Field::Sha1Key => Sha1::digest(&cert[key]).to_vec(),
+            Field::Sha256Subject => Sha256::digest(&cert[subject]).to_vec(),
+            Field::Sha256Key => Sha256::digest(&cert[key]).to_vec(),
+        })
+    }
+}
+
+/* The subject Name and the bits of the subjectPublicKey BIT STRING */
+fn subject_and_key(cert: &[u8]) -> Option<(Range<usize>, Range<usize>)> {
+    let certificate = read_element(cert, 0, cert.len()).filter(|x| x.tag == 0x30)?;
+    let tbs = children(cert, &certificate).next().filter(|x| x.tag == 0x30)?;
+    /* serialNumber, signature, issuer, validity, subject, subjectPublicKeyInfo */
+    let mut fields = children(cert, &tbs).skip_while(|x| x.tag == 0xa0);
+    let subject = fields.nth(4).filter(|x| x.tag == 0x30)?;
+    let spki = fields.next().filter(|x| x.tag == 0x30)?;
+    let key = children(cert, &spki).nth(1).filter(|x| x.tag == 0x03 && !x.contents.is_empty())?;
+    Some((subject.range(), key.contents.start + 1..key.contents.end))
+}
+
+/* The certificates in BasicOCSPResponse.certs:
+OCSPResponse > [0] responseBytes > SEQUENCE > response OCTET STRING > BasicOCSPResponse > [0] > SEQUENCE */
+fn embedded_certs(response: &[u8]) -> Vec<Range<usize>> {
+    let certs = || {
+        let ocsp = read_element(response, 0, response.len()).filter(|x| x.tag == 0x30)?;
+        let wrapper = children(response, &ocsp).find(|x| x.tag == 0xa0)?;
+        let response_bytes = children(response, &wrapper).next().filter(|x| x.tag == 0x30)?;
+        let octets = children(response, &response_bytes).nth(1).filter(|x| x.tag == 0x04)?;
+        let basic = read_element(response, octets.contents.start, octets.contents.end)
+            .filter(|x| x.tag == 0x30)?;
+        let wrapper = children(response, &basic).find(|x| x.tag == 0xa0)?;
+        let certs = children(response, &wrapper).next().filter(|x| x.tag == 0x30)?;
+        Some(children(response, &certs).filter(|x| x.tag == 0x30).map(|x| x.range()).collect())
+    };
+    certs().unwrap_or_default()
+}
+
+/* A value which can be replaced with a reference */
+struct Pattern<'a> {
+    value: Vec<u8>,
+    field: Field,
+    identifier: &'a [u8],
+}
+
+fn patterns<'a>(identifiers: &[&'a [u8]], dictionary: &dyn CertDictionary) -> Vec<Pattern<'a>> {
+    let mut patterns = Vec::new();
+    for identifier in identifiers {
+        let Some(cert) = dictionary.identifier_to_cert(identifier) else {
+            continue;
+        };
+        for field in FIELDS {
+            if let Some(value) = field.value(cert).filter(|x| x.len() >= MIN_MATCH_LEN) {
+                patterns.push(Pattern {
+                    value,
+                    field,
+                    identifier,
+                });
+            }
+        }
+    }
+    /* Whole certificates are preferred to the names inside them */
+    patterns.sort_by_key(|x| std::cmp::Reverse(x.value.len()));
+    patterns
+}
+
+fn write_literal(mut literal: &[u8], output: &mut Vec<u8>) {
+    while !literal.is_empty() {
+        let len = literal.len().min(u16::MAX as usize);
+        output.push(LITERAL);
+        output.extend_from_slice(&(len as u16).to_be_bytes());
+        output.extend_from_slice(&literal[..len]);
+        literal = &literal[len..];
+    }
+}
+
+fn abridge_response(response: &[u8], patterns: &[Pattern]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(response.len());
+    let mut literal_start = 0;
+    let mut position = 0;
+    while position < response.len() {
+        match patterns.iter().find(|x| response[position..].starts_with(&x.value)) {
+            Some(pattern) => {
+                write_literal(&response[literal_start..position], &mut output);
+                output.push(pattern.field as u8);
+                output.extend_from_slice(pattern.identifier);
+                position += pattern.value.len();
+                literal_start = position;
+            }
+            None => position += 1,
+        }
+    }
+    write_literal(&response[literal_start..], &mut output);
+    output
+}
+
+/* `offset` locates `ops` within the message for error reporting */
+fn expand_response(
+    ops: &[u8],
+    dictionary: &dyn CertDictionary,
+    offset: usize,
+    entry: usize,
+    max_size: usize,
+) -> Result<Vec<u8>, Error> {
+    let malformed = || Error::MalformedExtension(STATUS_REQUEST);
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < ops.len() {
+        if ops[position] == LITERAL {
+            let len = ops.get(position + 1..position + 3).ok_or_else(malformed)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let literal = ops.get(position + 3..position + 3 + len).ok_or_else(malformed)?;
+            output.extend_from_slice(literal);
+            position += 3 + len;
+        } else {
+            let field = Field::from_u8(ops[position]).ok_or_else(malformed)?;
+            let identifier = ops.get(position + 1..position + 1 + IDENTIFIER_LEN).ok_or_else(malformed)?;
+            let cert = dictionary.identifier_to_cert(identifier).ok_or_else(|| Error::UnknownIdentifier {
+                identifier: identifier.to_vec(),
+                offset: offset + position + 1,
+                entry,
+            })?;
+            output.extend_from_slice(&field.value(cert).ok_or_else(malformed)?);
+            position += 1 + IDENTIFIER_LEN;
+        }
+        if output.len() > max_size {
+            return Err(Error::SizeLimitExceeded { limit: max_size });
+        }
+    }
+    Ok(output)
+}
+
+/* Rewrites the status_request extensions of each entry with `f`, returning the new extensions
+block of every entry where one changed */
+fn map_staples(
+    msg: &CertificateMessage,
+    mut f: impl FnMut(usize, usize, &Extension) -> Result<Option<Vec<u8>>, Error>,
+) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let mut replacements = Vec::with_capacity(msg.certificate_entries.len());
+    let mut offset = 1 + msg.request_context.len() + 3;
+    for (index, entry) in msg.certificate_entries.iter().enumerate() {
+        let mut data_offset = offset + 3 + entry.data.len() + 2;
+        offset += entry.encoded_len();
+        let Ok(mut extensions) = entry.parse_extensions() else {
+            replacements.push(None);
+            continue;
+        };
+        let mut staples = Vec::with_capacity(extensions.len());
+        for extension in &extensions {
+            staples.push(match extension.extension_type {
+                STATUS_REQUEST => f(index, data_offset + 4, extension)?,
+                _ => None,
+            });
+            data_offset += extension.encoded_len();
+        }
+        if staples.iter().all(Option::is_none) {
+            replacements.push(None);
+            continue;
+        }
+        for (extension, staple) in extensions.iter_mut().zip(&staples) {
+            if let Some(staple) = staple {
+                extension.data = staple;
+            }
+        }
+        replacements.push(Some(encode_extensions(&extensions)?));
+    }
+    Ok(replacements)
+}
+
+/* Abridges a single staple, or returns `None` if that would not make it smaller. `chain` holds
+the identifiers of the dictionary certificates in the message. */
+fn abridge_staple(data: &[u8], chain: &[&[u8]], dictionary: &dyn CertDictionary) -> Option<Vec<u8>> {
+    let extension = Extension {
+        extension_type: STATUS_REQUEST,
+        data,
+    };
+    let Ok(ExtensionValue::OcspResponse(response)) = extension.decode() else {
+        return None;
+    };
+    let mut identifiers = chain.to_vec();
+    for cert in embedded_certs(response) {
+        identifiers.extend(dictionary.cert_to_identifier(&response[cert]));
+    }
+    identifiers.sort();
+    identifiers.dedup();
+    let mut abridged = vec![ABRIDGED_STATUS_TYPE];
+    abridged.extend(abridge_response(response, &patterns(&identifiers, dictionary)));
+    (abridged.len() < data.len()).then_some(abridged)
+}
+
+/// Abridges the OCSP responses stapled to each entry, returning the entries' new extensions.
+///
+/// Fails with [`Error::ReservedStatusType`] if a `status_request` extension would be mistaken for
+/// an abridged one.
+pub(super) fn abridge_staples(
+    msg: &CertificateMessage,
+    dictionary: &dyn CertDictionary,
+) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let chain: Vec<&[u8]> = msg
+        .certificate_entries
+        .iter()
+        .filter_map(|entry| dictionary.cert_to_identifier(entry.data))
+        .collect();
+    map_staples(msg, |_, _, extension| {
+        if extension.data.first() == Some(&ABRIDGED_STATUS_TYPE) {
+            return Err(Error::ReservedStatusType);
+        }
+        Ok(abridge_staple(extension.data, &chain, dictionary))
+    })
+}
+
+/// Reverses [`abridge_staples`], failing if the responses would total more than `max_size` bytes.
+///
+/// If `strict`, also fails with [`Error::NonCanonical`] unless abridging the result would
+/// reproduce `msg`'s staples exactly.
+pub(super) fn expand_staples(
+    msg: &CertificateMessage,
+    dictionary: &dyn CertDictionary,
+    max_size: usize,
+    strict: bool,
+) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    /* The same identifiers the compressor saw, since entries in the dictionary are always abridged */
+    let chain: Vec<&[u8]> = msg
+        .certificate_entries
+        .iter()
+        .map(|entry| entry.data)
+        .filter(|data| dictionary.identifier_to_cert(data).is_some())
+        .collect();
+    let mut total = 0;
+    map_staples(msg, |entry, offset, extension| {
+        if extension.data.first() != Some(&ABRIDGED_STATUS_TYPE) {
+            if strict && abridge_staple(extension.data, &chain, dictionary).is_some() {
+                return Err(Error::NonCanonical { offset, entry });
+            }
+            return Ok(None);
+        }
+        let response = expand_response(&extension.data[1..], dictionary, offset + 1, entry, max_size - total)
+            .map_err(|e| match e {
+                Error::SizeLimitExceeded { .. } => Error::SizeLimitExceeded { limit: max_size },
+                e => e,
+            })?;
+        total += response.len();
+        let expanded = ExtensionValue::OcspResponse(&response).to_vec()?;
+        if strict && abridge_staple(&expanded, &chain, dictionary).as_deref() != Some(extension.data) {
+            return Err(Error::NonCanonical { offset, entry });
+        }
+        Ok(Some(expanded))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::der::write_der_len;
+    use crate::pass1::{Compressor, Decompressor, LoadedDictionary};
+    use crate::tls::CertificateEntry;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+    use std::sync::Arc;
+
+    const SHA1: &[u8] = &[0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00];
+    const SHA256: &[u8] = &[
+        0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+    ];
+
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut output = vec![tag];
+        write_der_len(contents.len(), &mut output);
+        output.extend_from_slice(contents);
+        output
+    }
+
+    struct Pki {
+        ca: Vec<u8>,
+        responder: Vec<u8>,
+        leaf: Vec<u8>,
+        dictionary: Arc<LoadedDictionary>,
+    }
+
+    fn pki() -> Pki {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "OCSP Test CA");
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "OCSP Test Responder");
+        let responder = params.signed_by(&KeyPair::generate().unwrap(), &ca).unwrap();
+        let leaf = CertificateParams::new(vec!["ocsp.example".to_string()])
+            .unwrap()
+            .signed_by(&KeyPair::generate().unwrap(), &ca)
+            .unwrap();
+        let entries = vec![(vec![0xff, 0x12, 0x34], ca.der().to_vec())];
+        let dictionary = LoadedDictionary::from_entries("2026-10-18".into(), entries).unwrap();
+        Pki {
+            ca: ca.der().to_vec(),
+            responder: responder.der().to_vec(),
+            leaf: leaf.der().to_vec(),
+            dictionary: Arc::new(dictionary),
+        }
+    }
+
+    /* A successful BasicOCSPResponse with a single response for the CA's certificate serial 0x010203 */
+    fn ocsp_response(
+        responder_id: &[u8],
+        algorithm: &[u8],
+        name_hash: &[u8],
+        key_hash: &[u8],
+        certs: &[&[u8]],
+    ) -> Vec<u8> {
+        let time = tlv(0x18, b"20261018000000Z");
+        let cert_id = tlv(
+            0x30,
+            &[algorithm, &tlv(0x04, name_hash), &tlv(0x04, key_hash), &tlv(0x02, &[1, 2, 3])].concat(),
+        );
+        let single = tlv(0x30, &[&cert_id[..], &[0x80, 0x00], &time].concat());
+        let data = tlv(0x30, &[responder_id, &time, &tlv(0x30, &single)].concat());
+        let signature_algorithm = [
+            0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00,
+        ];
+        let signature = tlv(0x03, &[&[0][..], &[0x5a; 64]].concat());
+        let mut basic = [&data[..], &signature_algorithm, &signature].concat();
+        if !certs.is_empty() {
+            basic.extend(tlv(0xa0, &tlv(0x30, &certs.concat())));
+        }
+        let basic_oid = [0x06, 0x09, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+        let response_bytes = tlv(0x30, &[&basic_oid[..], &tlv(0x04, &tlv(0x30, &basic))].concat());
+        tlv(0x30, &[&[0x0a, 0x01, 0x00][..], &tlv(0xa0, &response_bytes)].concat())
+    }
+
+    /* The leaf, with `status_request` data and another extension, followed by the CA */
+    fn message(pki: &Pki, status_request: &[u8]) -> Vec<u8> {
+        let extensions = encode_extensions(&[
+            Extension {
+                extension_type: 0x1234,
+                data: &[1, 2, 3],
+            },
+            Extension {
+                extension_type: STATUS_REQUEST,
+                data: status_request,
+            },
+        ])
+        .unwrap();
+        CertificateMessage {
+            request_context: &[],
+            certificate_entries: vec![
+                CertificateEntry {
+                    data: &pki.leaf,
+                    extensions: &extensions,
+                },
+                CertificateEntry {
+                    data: &pki.ca,
+                    extensions: &[],
+                },
+            ],
+        }
+        .to_vec()
+        .unwrap()
+    }
+
+    fn staple(response: &[u8]) -> Vec<u8> {
+        ExtensionValue::OcspResponse(response).to_vec().unwrap()
+    }
+
+    /* Checks the round trip and returns how many bytes abridging the staple saved */
+    fn saving(pki: &Pki, msg: &[u8]) -> usize {
+        let plain = Compressor::new(pki.dictionary.clone()).compress_to_bytes(msg).unwrap();
+        let c = Compressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        let d = Decompressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        let out = c.compress_to_bytes(msg).unwrap();
+        assert_eq!(d.decompress_to_bytes(&out).unwrap(), msg);
+        plain.len() - out.len()
+    }
+
+    #[test]
+    fn delegated_responder() {
+        let pki = pki();
+        let responder_key = Field::Sha1Key.value(&pki.responder).unwrap();
+        let response = ocsp_response(
+            &tlv(0xa2, &tlv(0x04, &responder_key)),
+            SHA1,
+            &Field::Sha1Subject.value(&pki.ca).unwrap(),
+            &Field::Sha1Key.value(&pki.ca).unwrap(),
+            &[&pki.responder],
+        );
+        assert_eq!(embedded_certs(&response).len(), 1);
+        /* Both CertID hashes and the responder certificate's issuer become 4 byte references, each
+        starting a new 3 byte literal header */
+        let subject = Field::Subject.value(&pki.ca).unwrap();
+        assert!(saving(&pki, &message(&pki, &staple(&response))) >= 2 * 20 + subject.len() - 3 * (4 + 3));
+    }
+
+    #[test]
+    fn ca_signed_response() {
+        let pki = pki();
+        let response = ocsp_response(
+            &tlv(0xa1, &Field::Subject.value(&pki.ca).unwrap()),
+            SHA256,
+            &Field::Sha256Subject.value(&pki.ca).unwrap(),
+            &Field::Sha256Key.value(&pki.ca).unwrap(),
+            &[&pki.ca],
+        );
+        /* The embedded CA is replaced whole rather than by its subject */
+        assert!(saving(&pki, &message(&pki, &staple(&response))) > pki.ca.len());
+    }
+
+    #[test]
+    fn unrelated_staples() {
+        let pki = pki();
+        let response = ocsp_response(&tlv(0xa2, &tlv(0x04, &[7; 20])), SHA1, &[8; 20], &[9; 20], &[]);
+        for status_request in [staple(&response), vec![2, 0, 0]] {
+            let msg = message(&pki, &status_request);
+            assert_eq!(saving(&pki, &msg), 0);
+        }
+    }
+
+    #[test]
+    fn invalid_staples() {
+        let pki = pki();
+        let c = Compressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        let d = Decompressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        let msg = message(&pki, &[ABRIDGED_STATUS_TYPE, 0, 0, 1, 0]);
+        assert!(matches!(c.compress_to_bytes(&msg), Err(Error::ReservedStatusType)));
+
+        /* References to certificates the dictionary lacks, malformed operations and oversized output */
+        let msg = message(&pki, &[ABRIDGED_STATUS_TYPE, Field::Subject as u8, 0xff, 0x99, 0x99]);
+        let out = Compressor::new(pki.dictionary.clone()).compress_to_bytes(&msg).unwrap();
+        assert!(matches!(
+            d.decompress_to_bytes(&out),
+            Err(Error::UnknownIdentifier { entry: 0, .. })
+        ));
+        for ops in [&[0x07, 0xff, 0x12, 0x34][..], &[LITERAL, 0, 5, 1], &[Field::Sha1Key as u8, 0xff]] {
+            let msg = message(&pki, &[&[ABRIDGED_STATUS_TYPE][..], ops].concat());
+            let out = Compressor::new(pki.dictionary.clone()).compress_to_bytes(&msg).unwrap();
+            assert!(matches!(
+                d.decompress_to_bytes(&out),
+                Err(Error::MalformedExtension(STATUS_REQUEST))
+            ));
+        }
+        let msg = message(&pki, &[ABRIDGED_STATUS_TYPE, Field::Certificate as u8, 0xff, 0x12, 0x34]);
+        let out = Compressor::new(pki.dictionary.clone()).compress_to_bytes(&msg).unwrap();
+        assert!(matches!(
+            d.decompress_to_bytes_with_limit(&out, pki.ca.len() - 1),
+            Err(Error::SizeLimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn strict_staples() {
+        let pki = pki();
+        let response = ocsp_response(
+            &tlv(0xa1, &Field::Subject.value(&pki.ca).unwrap()),
+            SHA1,
+            &Field::Sha1Subject.value(&pki.ca).unwrap(),
+            &Field::Sha1Key.value(&pki.ca).unwrap(),
+            &[],
+        );
+        let msg = message(&pki, &staple(&response));
+        let c = Compressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        let d = Decompressor::new(pki.dictionary.clone()).with_ocsp_staples(true).with_strict(true);
+        let out = c.compress_to_bytes(&msg).unwrap();
+        assert_eq!(d.decompress_to_bytes(&out).unwrap(), msg);
+
+        /* A staple left whole, or abridged differently, is rejected */
+        let plain = Compressor::new(pki.dictionary.clone()).compress_to_bytes(&msg).unwrap();
+        assert!(matches!(d.decompress_to_bytes(&plain), Err(Error::NonCanonical { entry: 0, .. })));
+        let literal = [&[ABRIDGED_STATUS_TYPE][..], &abridge_response(&response, &[])].concat();
+        let out = Compressor::new(pki.dictionary.clone())
+            .compress_to_bytes(&message(&pki, &literal))
+            .unwrap();
+        let lenient = Decompressor::new(pki.dictionary.clone()).with_ocsp_staples(true);
+        assert_eq!(lenient.decompress_to_bytes(&out).unwrap(), msg);
+        assert!(matches!(d.decompress_to_bytes(&out), Err(Error::NonCanonical { entry: 0, .. })));
+    }
+}
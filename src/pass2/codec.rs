@@ -0,0 +1,316 @@
+use super::Dictionary;
+use crate::Error;
+use brotli::Allocator;
+use std::cell::Cell;
+use std::io::{ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+
+const BROTLI_BUFFER_SIZE: usize = 10_000;
+const BROTLI_Q: u32 = 11;
+const BROTLI_LGWIN: u32 = 12;
+const BROTLI_MAX_LGWIN: u32 = 24;
+/* Room left in the window for the message itself when a dictionary is in use */
+const BROTLI_MESSAGE_ALLOWANCE: usize = 16_384;
+const ZSTD_LEVEL: i32 = 19;
+/* Settings for compression done during a handshake, where latency matters more than size */
+const BROTLI_INTERACTIVE_Q: u32 = 5;
+const ZSTD_INTERACTIVE_LEVEL: i32 = 3;
+
+/// The wire format written by a [`Codec`], whatever its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Brotli,
+    Zstd,
+}
+
+/// The general purpose compressor used for pass 2.
+///
+/// The parameters only affect compression. A `Decompressor` can read the output of a `Compressor`
+/// using the same codec with any parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Brotli { quality: u32, lgwin: u32 },
+    Zstd { level: i32 },
+}
+
+impl Codec {
+    pub const fn brotli() -> Self {
+        Codec::Brotli {
+            quality: BROTLI_Q,
+            lgwin: BROTLI_LGWIN,
+        }
+    }
+
+    pub const fn zstd() -> Self {
+        Codec::Zstd { level: ZSTD_LEVEL }
+    }
+
+    /// A cheaper setting of the same codec, for compressing while a handshake waits.
+    pub fn interactive(&self) -> Self {
+        match *self {
+            Codec::Brotli { quality, lgwin } => Codec::Brotli {
+                quality: quality.min(BROTLI_INTERACTIVE_Q),
+                lgwin,
+            },
+            Codec::Zstd { level } => Codec::Zstd {
+                level: level.min(ZSTD_INTERACTIVE_LEVEL),
+            },
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Codec::Brotli { .. } => Format::Brotli,
+            Codec::Zstd { .. } => Format::Zstd,
+        }
+    }
+
+    /// Whether data compressed by `self` can be decompressed by `other`.
+    pub fn same_format(&self, other: &Codec) -> bool {
+        self.format() == other.format()
+    }
+
+    pub(super) fn compress(
+        &self,
+        msg: &[u8],
+        dictionary: Option<&Prepared>,
+        output: &mut impl Write,
+    ) -> std::io::Result<()> {
+        match (*self, dictionary) {
+            (Codec::Brotli { quality, lgwin }, None) => {
+                let mut writer = brotli::CompressorWriter::new(output, BROTLI_BUFFER_SIZE, quality, lgwin);
+                writer.write_all(msg)?;
+                writer.flush()?;
+            }
+            (Codec::Brotli { quality, lgwin }, Some(Prepared { dictionary, .. })) => {
+                let params = brotli::enc::BrotliEncoderParams {
+                    quality: quality as i32,
+                    lgwin: lgwin.max(lgwin_for(dictionary)) as i32,
+                    ..Default::default()
+                };
+                let mut input_buffer = vec![0; BROTLI_BUFFER_SIZE];
+                let mut output_buffer = vec![0; BROTLI_BUFFER_SIZE];
+                brotli::BrotliCompressCustomIoCustomDict(
+                    &mut brotli::IoReaderWrapper(&mut &msg[..]),
+                    &mut brotli::IoWriterWrapper(output),
+                    &mut input_buffer,
+                    &mut output_buffer,
+                    &params,
+                    brotli::enc::StandardAlloc::default(),
+                    &mut |_, _, _, _| (),
+                    dictionary.as_bytes(),
+                    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Unexpected EOF"),
+                )?;
+            }
+            (Codec::Zstd { level }, dictionary) => {
+                let mut compressor = match dictionary {
+                    Some(Prepared { zstd_encoder: Some(prepared), .. }) => {
+                        zstd::bulk::Compressor::with_prepared_dictionary(prepared)?
+                    }
+                    Some(Prepared { dictionary, .. }) => {
+                        zstd::bulk::Compressor::with_dictionary(level, dictionary.as_bytes())?
+                    }
+                    None => zstd::bulk::Compressor::new(level)?,
+                };
+                output.write_all(&compressor.compress(msg)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors caused by the compressed data ending early are reported as `UnexpectedEof`.
+    pub(super) fn decoder<'a>(
+        &self,
+        comp_msg: &'a [u8],
+        dictionary: Option<&'a Prepared>,
+    ) -> Result<Box<dyn Read + 'a>, Error> {
+        let exhausted = Rc::new(Cell::new(false));
+        let comp_msg = Input {
+            remaining: comp_msg,
+            exhausted: exhausted.clone(),
+        };
+        let inner: Box<dyn Read + 'a> = match (self, dictionary) {
+            (Codec::Brotli { .. }, None) => Box::new(brotli::Decompressor::new(comp_msg, BROTLI_BUFFER_SIZE)),
+            (Codec::Brotli { .. }, Some(Prepared { dictionary, .. })) => {
+                Box::new(BrotliReader(BrotliDecoder::new_with_custom_dictionary(
+                    brotli::IntoIoReader(comp_msg),
+                    BrotliAlloc.alloc_cell(BROTLI_BUFFER_SIZE),
+                    BrotliAlloc,
+                    brotli::enc::StandardAlloc::default(),
+                    brotli::enc::StandardAlloc::default(),
+                    BrotliMemory::Shared(dictionary.shared_bytes()),
+                    std::io::Error::new(ErrorKind::InvalidData, "Invalid Data"),
+                )))
+            }
+            (Codec::Zstd { .. }, None) => Box::new(zstd::stream::read::Decoder::new(comp_msg).map_err(Error::Codec)?),
+            (Codec::Zstd { .. }, Some(Prepared { zstd_decoder: Some(prepared), .. })) => {
+                let reader = std::io::BufReader::new(comp_msg);
+                let decoder = zstd::stream::read::Decoder::with_prepared_dictionary(reader, prepared);
+                Box::new(decoder.map_err(Error::Codec)?)
+            }
+            (Codec::Zstd { .. }, Some(Prepared { dictionary, .. })) => {
+                let reader = std::io::BufReader::new(comp_msg);
+                let decoder = zstd::stream::read::Decoder::with_dictionary(reader, dictionary.as_bytes());
+                Box::new(decoder.map_err(Error::Codec)?)
+            }
+        };
+        Ok(Box::new(Decoder { inner, exhausted }))
+    }
+}
+
+/* The codecs report running out of input in the same way as malformed input, so the reader
+feeding them records whether they asked for more data after the end of the message */
+struct Input<'a> {
+    remaining: &'a [u8],
+    exhausted: Rc<Cell<bool>>,
+}
+
+impl Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining.is_empty() && !buf.is_empty() {
+            self.exhausted.set(true);
+        }
+        self.remaining.read(buf)
+    }
+}
+
+struct Decoder<'a> {
+    inner: Box<dyn Read + 'a>,
+    exhausted: Rc<Cell<bool>>,
+}
+
+impl Read for Decoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            if self.exhausted.get() {
+                std::io::Error::new(ErrorKind::UnexpectedEof, e)
+            } else {
+                e
+            }
+        })
+    }
+}
+
+/* A pass 2 dictionary with whatever a codec derives from it. zstd digests a dictionary before use,
+for a particular level when compressing, so that is done once for each compressor or decompressor
+rather than for every message */
+pub(super) struct Prepared {
+    dictionary: Dictionary,
+    zstd_encoder: Option<zstd::dict::EncoderDictionary<'static>>,
+    zstd_decoder: Option<zstd::dict::DecoderDictionary<'static>>,
+}
+
+impl Prepared {
+    pub(super) fn for_compression(codec: Codec, dictionary: Dictionary) -> Self {
+        let zstd_encoder = match codec {
+            Codec::Zstd { level } => Some(zstd::dict::EncoderDictionary::copy(dictionary.as_bytes(), level)),
+            Codec::Brotli { .. } => None,
+        };
+        Prepared {
+            dictionary,
+            zstd_encoder,
+            zstd_decoder: None,
+        }
+    }
+
+    pub(super) fn for_decompression(codec: Codec, dictionary: Dictionary) -> Self {
+        let zstd_decoder = match codec {
+            Codec::Zstd { .. } => Some(zstd::dict::DecoderDictionary::copy(dictionary.as_bytes())),
+            Codec::Brotli { .. } => None,
+        };
+        Prepared {
+            dictionary,
+            zstd_encoder: None,
+            zstd_decoder,
+        }
+    }
+
+    pub(super) fn dictionary(&self) -> &Dictionary {
+        &self.dictionary
+    }
+}
+
+impl std::fmt::Debug for Prepared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.dictionary.fmt(f)
+    }
+}
+
+/* The Brotli decoder takes ownership of its dictionary, copying it into its window and then freeing
+it. Its memory type can hold a shared dictionary, so that decoding doesn't allocate a copy first */
+enum BrotliMemory {
+    Owned(Box<[u8]>),
+    Shared(Arc<[u8]>),
+}
+
+impl Default for BrotliMemory {
+    fn default() -> Self {
+        BrotliMemory::Owned(Box::default())
+    }
+}
+
+impl brotli::SliceWrapper<u8> for BrotliMemory {
+    fn slice(&self) -> &[u8] {
+        match self {
+            BrotliMemory::Owned(bytes) => bytes,
+            BrotliMemory::Shared(bytes) => bytes,
+        }
+    }
+}
+
+impl brotli::SliceWrapperMut<u8> for BrotliMemory {
+    fn slice_mut(&mut self) -> &mut [u8] {
+        /* The decoder only reads its dictionary, but copy rather than fail if that changes */
+        if let BrotliMemory::Shared(bytes) = self {
+            *self = BrotliMemory::Owned(bytes.to_vec().into());
+        }
+        match self {
+            BrotliMemory::Owned(bytes) => bytes,
+            BrotliMemory::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+struct BrotliAlloc;
+
+impl brotli::Allocator<u8> for BrotliAlloc {
+    type AllocatedMemory = BrotliMemory;
+
+    fn alloc_cell(&mut self, len: usize) -> BrotliMemory {
+        BrotliMemory::Owned(vec![0; len].into())
+    }
+
+    fn free_cell(&mut self, _data: BrotliMemory) {}
+}
+
+type BrotliDecoder<R> = brotli::DecompressorCustomIo<
+    std::io::Error,
+    brotli::IntoIoReader<R>,
+    BrotliMemory,
+    BrotliAlloc,
+    brotli::enc::StandardAlloc,
+    brotli::enc::StandardAlloc,
+>;
+
+struct BrotliReader<R: Read>(BrotliDecoder<R>);
+
+impl<R: Read> Read for BrotliReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        brotli::CustomRead::read(&mut self.0, buf)
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::brotli()
+    }
+}
+
+/* Brotli can only refer back as far as its window, so the window has to span the dictionary as well */
+fn lgwin_for(dictionary: &Dictionary) -> u32 {
+    let needed = dictionary.as_bytes().len() + BROTLI_MESSAGE_ALLOWANCE;
+    (BROTLI_LGWIN..BROTLI_MAX_LGWIN)
+        .find(|lgwin| (1_usize << lgwin) - 16 >= needed)
+        .unwrap_or(BROTLI_MAX_LGWIN)
+}
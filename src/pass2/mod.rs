@@ -1,66 +1,202 @@
-use simple_error::SimpleError;
-use std::io::{Read, Write};
+use crate::Error;
+use rustls::compress::CompressionLevel;
+use std::io::{ErrorKind, Read, Write};
 
-const BROTLI_BUFFER_SIZE: usize = 10_000;
-const BROTLI_Q : u32 = 11;
-const BROTLI_LGWIN : u32 = 12;
+mod codec;
+mod dictionary;
+mod train;
+
+use codec::Prepared;
+pub use codec::{Codec, Format};
+pub use dictionary::{Dictionary, DICTIONARY_ID_LEN};
+pub use train::{residuals, weight_for_rank, Trainer};
+
+/* Every stream starts with one of these, so a decompressor can tell whether the compressor used
+a dictionary whatever its own configuration */
+const NO_DICTIONARY: u8 = 0x00;
+const WITH_DICTIONARY: u8 = 0x01;
+
+/// Compresses with `codec` when the result will be cached and reused, and with the cheaper
+/// `interactive_codec` when it is needed immediately.
 #[derive(Debug)]
 pub struct Compressor {
-
+    codec: Codec,
+    interactive_codec: Codec,
+    dictionary: Option<Prepared>,
+    interactive_dictionary: Option<Prepared>,
 }
 
 impl Compressor {
     pub fn new() -> Self {
+        Self::new_with_codec(Codec::default(), None)
+    }
+
+    pub fn new_with_dictionary(dictionary: Dictionary) -> Self {
+        Self::new_with_codec(Codec::default(), Some(dictionary))
+    }
+
+    pub fn new_with_codec(codec: Codec, dictionary: Option<Dictionary>) -> Self {
+        let interactive_codec = codec.interactive();
         Compressor {
+            codec,
+            interactive_codec,
+            interactive_dictionary: dictionary
+                .clone()
+                .map(|dictionary| Prepared::for_compression(interactive_codec, dictionary)),
+            dictionary: dictionary.map(|dictionary| Prepared::for_compression(codec, dictionary)),
+        }
+    }
+
+    pub fn new_from_builtin() -> Self {
+        Self::new_with_dictionary(Dictionary::builtin())
+    }
 
+    /// Overrides the settings used for `CompressionLevel::Interactive`, which default to
+    /// [`Codec::interactive`]. Both levels must use the same codec so that either can be decoded.
+    pub fn with_interactive_codec(mut self, codec: Codec) -> Result<Self, Error> {
+        if !codec.same_format(&self.codec) {
+            return Err(Error::IncompatibleCodecs);
         }
+        self.interactive_codec = codec;
+        self.interactive_dictionary = self
+            .dictionary()
+            .cloned()
+            .map(|dictionary| Prepared::for_compression(codec, dictionary));
+        Ok(self)
     }
 
-    pub fn compress<T: Write>(
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn codec_for(&self, level: CompressionLevel) -> Codec {
+        match level {
+            CompressionLevel::Interactive => self.interactive_codec,
+            CompressionLevel::Amortized => self.codec,
+        }
+    }
+
+    pub fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_ref().map(Prepared::dictionary)
+    }
+
+    /// Compresses at `CompressionLevel::Amortized`.
+    pub fn compress<T: Write>(&self, msg: &[u8], output: T) -> Result<T, Error> {
+        self.compress_with_level(msg, CompressionLevel::Amortized, output)
+    }
+
+    pub fn compress_with_level<T: Write>(
         &self,
         msg: &[u8],
-        output: T,
-    ) -> Result<T, Box<dyn std::error::Error>> {
-        let mut writer = brotli::CompressorWriter::new(output, BROTLI_BUFFER_SIZE, BROTLI_Q, BROTLI_LGWIN);
-        writer.write_all(msg)?;
-        writer.flush()?;
-        Ok(writer.into_inner())
+        level: CompressionLevel,
+        mut output: T,
+    ) -> Result<T, Error> {
+        match self.dictionary() {
+            Some(dictionary) => {
+                output.write_all(&[WITH_DICTIONARY])?;
+                output.write_all(&dictionary.id())?;
+            }
+            None => output.write_all(&[NO_DICTIONARY])?,
+        }
+        let dictionary = match level {
+            CompressionLevel::Interactive => self.interactive_dictionary.as_ref(),
+            CompressionLevel::Amortized => self.dictionary.as_ref(),
+        };
+        self.codec_for(level)
+            .compress(msg, dictionary, &mut output)
+            .map_err(Error::Codec)?;
+        Ok(output)
+    }
+
+    pub fn compress_to_bytes(&self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        self.compress_to_bytes_with_level(msg, CompressionLevel::Amortized)
     }
 
-    pub fn compress_to_bytes(&self, msg: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    pub fn compress_to_bytes_with_level(
+        &self,
+        msg: &[u8],
+        level: CompressionLevel,
+    ) -> Result<Vec<u8>, Error> {
         let output = Vec::with_capacity(msg.len());
         let mut writer = std::io::Cursor::new(output);
-        writer = self.compress(msg, writer)?;
+        writer = self.compress_with_level(msg, level, writer)?;
         Ok(writer.into_inner())
     }
 }
 
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct Decompressor {
+    codec: Codec,
+    dictionary: Option<Prepared>,
 }
 
 impl Decompressor {
     pub fn new() -> Self {
+        Self::new_with_codec(Codec::default(), None)
+    }
+
+    pub fn new_with_dictionary(dictionary: Dictionary) -> Self {
+        Self::new_with_codec(Codec::default(), Some(dictionary))
+    }
+
+    pub fn new_with_codec(codec: Codec, dictionary: Option<Dictionary>) -> Self {
         Decompressor {
+            codec,
+            dictionary: dictionary.map(|dictionary| Prepared::for_decompression(codec, dictionary)),
         }
     }
 
+    pub fn new_from_builtin() -> Self {
+        Self::new_with_dictionary(Dictionary::builtin())
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_ref().map(Prepared::dictionary)
+    }
+
     pub fn decompress_to_bytes(
         &self,
-        comp_msg: &[u8],
+        mut comp_msg: &[u8],
         max_size: u32,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let mut output_buf = vec![0; max_size as usize];
-        let mut decoder = brotli::Decompressor::new(comp_msg, BROTLI_BUFFER_SIZE);
-        let size = decoder.read(&mut output_buf)?;
-        output_buf.truncate(size);
-        output_buf.shrink_to(size);
-        let mut overflow = vec![0; 1];
-        if decoder.read_exact(&mut overflow).is_ok() {
-            output_buf.clear();
-            return Err(Box::new(SimpleError::new("Over long data!")));
+    ) -> Result<Vec<u8>, Error> {
+        let Some((&marker, rest)) = comp_msg.split_first() else {
+            return Err(Error::TruncatedStream);
+        };
+        comp_msg = match (marker, self.dictionary()) {
+            (NO_DICTIONARY, None) => rest,
+            (WITH_DICTIONARY, Some(dictionary))
+                if rest.len() >= DICTIONARY_ID_LEN && rest[..DICTIONARY_ID_LEN] == dictionary.id() =>
+            {
+                &rest[DICTIONARY_ID_LEN..]
+            }
+            _ => return Err(Error::DictionaryMismatch),
+        };
+        /* Read one byte past the limit so that exactly `max_size` bytes is distinguishable from too many */
+        let max_size = max_size as usize;
+        let mut output = Vec::with_capacity(comp_msg.len().saturating_mul(4).min(max_size));
+        let decoder = self.codec.decoder(comp_msg, self.dictionary.as_ref())?;
+        match decoder.take(max_size as u64 + 1).read_to_end(&mut output) {
+            Ok(_) if output.len() > max_size => Err(Error::SizeLimitExceeded { limit: max_size }),
+            Ok(_) => Ok(output),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(Error::TruncatedStream),
+            Err(e) => Err(Error::Codec(e)),
         }
-        Ok(output_buf)
+    }
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -120,6 +256,145 @@ mod tests {
         assert_eq!(cert_bytes, round_trip);
     }
 
+    #[test]
+    fn round_trip_dictionary() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        /* A dictionary which contains most of the message should make it nearly free */
+        let dictionary = Dictionary::new(cert_bytes[10..].to_vec());
+        let plain = Compressor::new().compress_to_bytes(&cert_bytes).unwrap();
+        let c = Compressor::new_with_dictionary(dictionary.clone());
+        let out = c
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        assert!(out.len() < plain.len() / 4, "{} vs {}", out.len(), plain.len());
+        let c = Decompressor::new_with_dictionary(dictionary);
+        let round_trip = c
+            .decompress_to_bytes(&out, 16000)
+            .expect("Decompression succeeds");
+        assert_eq!(cert_bytes, round_trip);
+    }
+
+    #[test]
+    fn round_trip_builtin() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let out = Compressor::new_from_builtin()
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let round_trip = Decompressor::new_from_builtin()
+            .decompress_to_bytes(&out, 16000)
+            .expect("Decompression succeeds");
+        assert_eq!(cert_bytes, round_trip);
+    }
+
+    #[test]
+    fn dictionary_mismatch() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let c = Compressor::new_with_dictionary(Dictionary::new(b"one dictionary".to_vec()));
+        let out = c
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let c = Decompressor::new_with_dictionary(Dictionary::new(b"another dictionary".to_vec()));
+        let err = c
+            .decompress_to_bytes(&out, 16000)
+            .expect_err("Dictionaries differ");
+        assert!(matches!(err, Error::DictionaryMismatch));
+        let plain = Compressor::new().compress_to_bytes(&cert_bytes).unwrap();
+        let err = c
+            .decompress_to_bytes(&plain, 16000)
+            .expect_err("Compressed without a dictionary");
+        assert!(matches!(err, Error::DictionaryMismatch));
+        for codec in [Codec::brotli(), Codec::zstd()] {
+            let out = Compressor::new_with_codec(codec, Some(Dictionary::new(b"one dictionary".to_vec())))
+                .compress_to_bytes(&cert_bytes)
+                .expect("Compression succeeds");
+            let err = Decompressor::new_with_codec(codec, None)
+                .decompress_to_bytes(&out, 16000)
+                .expect_err("Compressed with a dictionary");
+            assert!(matches!(err, Error::DictionaryMismatch), "{:?}: {}", codec, err);
+        }
+        let err = Decompressor::new().decompress_to_bytes(&[], 16000).expect_err("No marker");
+        assert!(matches!(err, Error::TruncatedStream));
+    }
+
+    #[test]
+    fn round_trip_zstd() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        for dictionary in [None, Some(Dictionary::new(cert_bytes[10..].to_vec()))] {
+            let c = Compressor::new_with_codec(Codec::zstd(), dictionary.clone());
+            let out = c
+                .compress_to_bytes(&cert_bytes)
+                .expect("Compression succeeds");
+            let c = Decompressor::new_with_codec(Codec::zstd(), dictionary);
+            let round_trip = c
+                .decompress_to_bytes(&out, 16000)
+                .expect("Decompression succeeds");
+            assert_eq!(cert_bytes, round_trip);
+        }
+    }
+
+    #[test]
+    fn levels_round_trip() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        for codec in [Codec::brotli(), Codec::zstd()] {
+            for dictionary in [None, Some(Dictionary::new(cert_bytes[10..].to_vec()))] {
+                let c = Compressor::new_with_codec(codec, dictionary.clone());
+                assert_ne!(
+                    c.codec_for(CompressionLevel::Interactive),
+                    c.codec_for(CompressionLevel::Amortized)
+                );
+                let d = Decompressor::new_with_codec(codec, dictionary);
+                for level in [CompressionLevel::Interactive, CompressionLevel::Amortized] {
+                    let out = c
+                        .compress_to_bytes_with_level(&cert_bytes, level)
+                        .expect("Compression succeeds");
+                    let round_trip = d
+                        .decompress_to_bytes(&out, 16000)
+                        .expect("Decompression succeeds");
+                    assert_eq!(cert_bytes, round_trip);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interactive_codec_is_configurable() {
+        let c = Compressor::new()
+            .with_interactive_codec(Codec::Brotli { quality: 1, lgwin: 10 })
+            .expect("Same codec");
+        assert_eq!(
+            c.codec_for(CompressionLevel::Interactive),
+            Codec::Brotli { quality: 1, lgwin: 10 }
+        );
+        assert_eq!(c.codec_for(CompressionLevel::Amortized), Codec::brotli());
+        assert!(matches!(
+            Compressor::new().with_interactive_codec(Codec::zstd()),
+            Err(Error::IncompatibleCodecs)
+        ));
+    }
+
+    #[test]
+    fn codec_mismatch() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let out = Compressor::new_with_codec(Codec::zstd(), None)
+            .compress_to_bytes(&cert_bytes)
+            .expect("Compression succeeds");
+        let _ = Decompressor::new()
+            .decompress_to_bytes(&out, 16000)
+            .expect_err("Brotli cannot read zstd");
+    }
+
     #[test]
     fn size_limits() {
         let mut cert_hex: String = String::from(CERTMSG);
@@ -130,14 +405,55 @@ mod tests {
             .compress_to_bytes(&cert_bytes)
             .expect("Compression succeeds");
         let c = Decompressor::new();
-        let _ = c
+        let err = c
             .decompress_to_bytes(&out, 100)
             .expect_err("Shouldn't be enough space!");
+        assert!(matches!(err, Error::SizeLimitExceeded { .. }));
+        let exact = c
+            .decompress_to_bytes(&out, cert_bytes.len() as u32)
+            .expect("Exactly enough space");
+        assert_eq!(cert_bytes, exact);
+        let err = c
+            .decompress_to_bytes(&out, cert_bytes.len() as u32 - 1)
+            .expect_err("One byte short");
+        assert!(matches!(err, Error::SizeLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn truncated_streams() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        for codec in [Codec::brotli(), Codec::zstd()] {
+            for dictionary in [None, Some(Dictionary::builtin())] {
+                let out = Compressor::new_with_codec(codec, dictionary.clone())
+                    .compress_to_bytes(&cert_bytes)
+                    .expect("Compression succeeds");
+                let c = Decompressor::new_with_codec(codec, dictionary);
+                let err = c
+                    .decompress_to_bytes(&out[..out.len() - 1], 16000)
+                    .expect_err("Stream is incomplete");
+                assert!(matches!(err, Error::TruncatedStream), "{:?}: {}", codec, err);
+            }
+        }
+    }
+
+    #[test]
+    fn large_outputs_are_not_truncated() {
+        /* Far larger than a single read from the decoder */
+        let msg: Vec<u8> = (0..40_000u32).map(|x| (x.wrapping_mul(2654435761) >> 24) as u8).collect();
+        for codec in [Codec::brotli(), Codec::zstd()] {
+            let out = Compressor::new_with_codec(codec, None).compress_to_bytes(&msg).unwrap();
+            let round_trip = Decompressor::new_with_codec(codec, None)
+                .decompress_to_bytes(&out, u32::MAX)
+                .expect("Decompression succeeds");
+            assert_eq!(msg, round_trip);
+        }
     }
 }
 
 #[cfg(test)]
-#[cfg(all(feature ="nightly-features"))]
+#[cfg(feature = "nightly-features")]
 mod datatests {
 
     #[datatest::files("data/certificate_messages", {
@@ -0,0 +1,183 @@
+use crate::pass1;
+use crate::tls::CertificateMessage;
+use crate::Error;
+use std::collections::HashMap;
+
+/* Length of the substrings used to measure how common a piece of a sample is */
+const DMER_LEN: usize = 8;
+/* Length of the segments copied from the samples into the dictionary */
+const SEGMENT_LEN: usize = 64;
+
+/// Builds a pass 2 dictionary from the material pass 1 leaves behind.
+///
+/// This is a simplified form of the COVER algorithm used by zstd: the samples are split into
+/// epochs, the segment from each epoch containing the most valuable unseen substrings is kept,
+/// and the best segments are placed at the end of the dictionary where references are cheapest.
+#[derive(Debug, Default)]
+pub struct Trainer {
+    samples: Vec<(Vec<u8>, f64)>,
+}
+
+/// Weights a sample by the Tranco rank of the site it was captured from. Popular sites carry
+/// more traffic, but the falloff is logarithmic so the long tail still contributes.
+pub fn weight_for_rank(rank: Option<u32>) -> f64 {
+    match rank {
+        Some(rank) => 1.0 / (1.0 + f64::from(rank.max(1)).log10()),
+        None => 0.1,
+    }
+}
+
+/// Returns the certificates in `cert_msg` which `compressor` could not replace with an identifier.
+pub fn residuals(
+    compressor: &pass1::Compressor,
+    cert_msg: &[u8],
+) -> Result<Vec<Vec<u8>>, Error> {
+    let compressed = compressor.compress_to_bytes(cert_msg)?;
+    let msg = CertificateMessage::read_from_bytes(&compressed)?;
+    Ok(msg
+        .certificate_entries
+        .into_iter()
+        .filter(|x| !pass1::is_valid_identifier(x.data))
+        .map(|x| x.data.to_vec())
+        .collect())
+}
+
+impl Trainer {
+    pub fn new() -> Self {
+        Trainer::default()
+    }
+
+    pub fn add_sample(&mut self, sample: Vec<u8>, weight: f64) {
+        if sample.len() >= DMER_LEN && weight > 0.0 {
+            self.samples.push((sample, weight));
+        }
+    }
+
+    /// Runs pass 1 over `cert_msg` and adds the residual certificates as samples.
+    pub fn add_certificate_message(
+        &mut self,
+        compressor: &pass1::Compressor,
+        cert_msg: &[u8],
+        weight: f64,
+    ) -> Result<(), Error> {
+        for residual in residuals(compressor, cert_msg)? {
+            self.add_sample(residual, weight);
+        }
+        Ok(())
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Produces a dictionary of at most `max_size` bytes.
+    pub fn train(&self, max_size: usize) -> Vec<u8> {
+        /* Each d-mer is scored by the total weight of the samples it occurs in */
+        let mut occurrences: HashMap<&[u8], (f64, usize)> = HashMap::new();
+        for (sample, weight) in &self.samples {
+            let mut seen: Vec<&[u8]> = sample.windows(DMER_LEN).collect();
+            seen.sort_unstable();
+            seen.dedup();
+            for dmer in seen {
+                let entry = occurrences.entry(dmer).or_default();
+                entry.0 += weight;
+                entry.1 += 1;
+            }
+        }
+        /* Substrings seen in a single sample are worthless to a shared dictionary */
+        let mut scores: HashMap<&[u8], f64> = occurrences
+            .into_iter()
+            .filter(|(_, (_, count))| *count > 1)
+            .map(|(dmer, (score, _))| (dmer, score))
+            .collect();
+
+        let positions: Vec<(usize, usize)> = self
+            .samples
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (sample, _))| {
+                (0..=sample.len().saturating_sub(SEGMENT_LEN)).map(move |p| (i, p))
+            })
+            .collect();
+        let epochs = max_size.div_ceil(SEGMENT_LEN).max(1);
+        let epoch_len = positions.len().div_ceil(epochs).max(1);
+
+        let mut segments = Vec::new();
+        for epoch in positions.chunks(epoch_len) {
+            if let Some((score, segment)) = self.best_segment(epoch, &scores) {
+                for dmer in segment.windows(DMER_LEN) {
+                    scores.remove(dmer);
+                }
+                segments.push((score, segment));
+            }
+        }
+
+        segments.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut size = 0;
+        segments.retain(|(_, segment)| {
+            size += segment.len();
+            size <= max_size
+        });
+        segments.iter().rev().flat_map(|(_, x)| x.iter()).copied().collect()
+    }
+
+    /* Finds the segment starting at one of `positions` whose distinct d-mers have the highest total score */
+    fn best_segment<'a>(
+        &'a self,
+        positions: &[(usize, usize)],
+        scores: &HashMap<&[u8], f64>,
+    ) -> Option<(f64, &'a [u8])> {
+        let mut best: Option<(f64, &[u8])> = None;
+        for (sample, start) in positions {
+            let sample = &self.samples[*sample].0;
+            let segment = &sample[*start..sample.len().min(start + SEGMENT_LEN)];
+            let mut dmers: Vec<&[u8]> = segment.windows(DMER_LEN).collect();
+            dmers.sort_unstable();
+            dmers.dedup();
+            let score: f64 = dmers.iter().filter_map(|x| scores.get(x)).sum();
+            if score > 0.0 && best.is_none_or(|(s, _)| score > s) {
+                best = Some((score, segment));
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_material_is_kept() {
+        let shared = b"Let's Encrypt Authority R3 OCSP http://r3.o.lencr.org CA Issuers";
+        let mut trainer = Trainer::new();
+        for i in 0..10u8 {
+            let mut sample = vec![i; 100];
+            sample.extend_from_slice(shared);
+            sample.extend(std::iter::repeat_n(i.wrapping_mul(37), 100));
+            trainer.add_sample(sample, 1.0);
+        }
+        let dictionary = trainer.train(1024);
+        assert!(dictionary.len() <= 1024);
+        assert!(dictionary.windows(32).any(|x| x == &shared[16..48]));
+    }
+
+    #[test]
+    fn size_is_bounded() {
+        let mut trainer = Trainer::new();
+        for i in 0..20u32 {
+            let sample: Vec<u8> = (0..500u32).map(|x| ((x * 7 + i % 3) % 251) as u8).collect();
+            trainer.add_sample(sample, weight_for_rank(Some(i + 1)));
+        }
+        for max_size in [64, 500, 4096] {
+            assert!(trainer.train(max_size).len() <= max_size);
+        }
+    }
+
+    #[test]
+    fn popular_sites_weigh_more() {
+        assert!(weight_for_rank(Some(1)) > weight_for_rank(Some(100)));
+        assert!(weight_for_rank(Some(100)) > weight_for_rank(Some(10_000)));
+        assert!(weight_for_rank(Some(1_000_000)) > weight_for_rank(None));
+    }
+}
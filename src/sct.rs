@@ -0,0 +1,591 @@
+//! An optional pass which abridges Signed Certificate Timestamps.
+//!
+//! Every SCT names the CT log which issued it with a 32 byte log ID. Servers send two or three
+//! SCTs per leaf, either embedded in the certificate or in a `signed_certificate_timestamp`
+//! extension, and they come from a small public set of logs. This pass replaces the IDs of logs in
+//! a [`LogDictionary`] with 2 byte codes. It runs after pass 1 and before pass 2.
+//!
+//! An abridged SCT replaces the version and log ID with [`ABRIDGED_SCT_VERSION`] and the code:
+//!
+//! ```text
+//! original: 0x00 | log_id[32] | timestamp | extensions | signature
+//! abridged: 0xff | code[2]    | timestamp | extensions | signature
+//! ```
+//!
+//! Length fields enclosing the SCT are rewritten to match, including the DER lengths of an
+//! embedding certificate. The output is prefixed with the dictionary's identifier.
+
+use crate::tls::{encode_extensions, CertificateMessage, Extension, ExtensionValue, SIGNED_CERTIFICATE_TIMESTAMP};
+use crate::der::{children, read_element, write_der_len, Element};
+use crate::Error;
+use base64::prelude::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const LOG_ID_LEN: usize = 32;
+pub const LOG_DICTIONARY_ID_LEN: usize = 4;
+/// Marks an abridged SCT. RFC 6962 only defines version 0.
+pub const ABRIDGED_SCT_VERSION: u8 = 0xff;
+const SCT_V1: u8 = 0;
+const CODE_LEN: usize = 2;
+/* The embedded SCT list extension, 1.3.6.1.4.1.11129.2.4.2, including its OID tag and length */
+const SCT_LIST_OID: &[u8] = &[0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+/// A CT log, as listed in a log list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CtLog {
+    pub log_id: [u8; LOG_ID_LEN],
+    /// The DER SubjectPublicKeyInfo whose SHA-256 hash is the log ID, if known.
+    pub key: Option<Vec<u8>>,
+    pub description: String,
+}
+
+/* The parts of Google's v3 log list schema which are needed here */
+#[derive(Deserialize)]
+struct LogList {
+    operators: Vec<Operator>,
+}
+
+#[derive(Deserialize)]
+struct Operator {
+    #[serde(default)]
+    logs: Vec<ListedLog>,
+    #[serde(default)]
+    tiled_logs: Vec<ListedLog>,
+}
+
+#[derive(Deserialize)]
+struct ListedLog {
+    #[serde(default)]
+    description: String,
+    log_id: String,
+    key: String,
+}
+
+/// Maps the IDs of known CT logs to 2 byte codes.
+///
+/// Codes are assigned in order of log ID, so peers which load the same set of logs agree on them
+/// whatever order their log lists were in. The identifier covers the whole set.
+#[derive(Debug, Clone)]
+pub struct LogDictionary {
+    logs: Vec<CtLog>,
+    codes: HashMap<[u8; LOG_ID_LEN], u16>,
+    id: [u8; LOG_DICTIONARY_ID_LEN],
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidDictionary(reason.into())
+}
+
+impl LogDictionary {
+    /// Builds a dictionary from `logs`, checking each key against its log ID. Logs listed more
+    /// than once are only included once.
+    pub fn new(mut logs: Vec<CtLog>) -> Result<Self, Error> {
+        for log in &logs {
+            if let Some(key) = &log.key {
+                if Sha256::digest(key)[..] != log.log_id {
+                    return Err(invalid(format!("Key does not match log ID for {}", log.description)));
+                }
+            }
+        }
+        logs.sort_by_key(|log| log.log_id);
+        logs.dedup_by(|a, b| a.log_id == b.log_id);
+        if logs.len() > u16::MAX as usize + 1 {
+            return Err(Error::IdentifiersExhausted);
+        }
+        let codes = logs.iter().enumerate().map(|(code, log)| (log.log_id, code as u16)).collect();
+        let mut hasher = Sha256::new();
+        for log in &logs {
+            hasher.update(log.log_id);
+        }
+        let mut id = [0; LOG_DICTIONARY_ID_LEN];
+        id.copy_from_slice(&hasher.finalize()[..LOG_DICTIONARY_ID_LEN]);
+        Ok(LogDictionary { logs, codes, id })
+    }
+
+    /// Reads every log, including tiled logs, from a log list in Google's v3 JSON format, e.g. a
+    /// local copy of `https://www.gstatic.com/ct/log_list/v3/all_logs_list.json`.
+    pub fn from_log_list(json: &str) -> Result<Self, Error> {
+        let list: LogList = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        let mut logs = Vec::new();
+        for log in list.operators.into_iter().flat_map(|x| x.logs.into_iter().chain(x.tiled_logs)) {
+            let decode = |value: &str| {
+                BASE64_STANDARD
+                    .decode(value)
+                    .map_err(|e| invalid(format!("Invalid base64 for {}: {}", log.description, e)))
+            };
+            let log_id = decode(&log.log_id)?
+                .try_into()
+                .map_err(|_| invalid(format!("Log ID of {} is not {} bytes", log.description, LOG_ID_LEN)))?;
+            logs.push(CtLog {
+                log_id,
+                key: Some(decode(&log.key)?),
+                description: log.description,
+            });
+        }
+        Self::new(logs)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_log_list(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn id(&self) -> [u8; LOG_DICTIONARY_ID_LEN] {
+        self.id
+    }
+
+    pub fn logs(&self) -> &[CtLog] {
+        &self.logs
+    }
+
+    pub fn code_for(&self, log_id: &[u8]) -> Option<u16> {
+        self.codes.get(log_id).copied()
+    }
+
+    pub fn log_id(&self, code: u16) -> Option<&[u8; LOG_ID_LEN]> {
+        self.logs.get(code as usize).map(|log| &log.log_id)
+    }
+}
+
+/* Rewrites each SCT in a SignedCertificateTimestampList with `f`, which returns `None` to keep an
+SCT as it is. Returns `None` if the list is malformed or nothing changed. */
+fn map_sct_list(
+    list: &[u8],
+    f: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, Error>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let extension = Extension {
+        extension_type: SIGNED_CERTIFICATE_TIMESTAMP,
+        data: list,
+    };
+    let Ok(ExtensionValue::SignedCertificateTimestamps(scts)) = extension.decode() else {
+        return Ok(None);
+    };
+    let mut changed = false;
+    let mut mapped = Vec::with_capacity(scts.len());
+    for sct in scts {
+        match f(sct)? {
+            Some(new) => {
+                changed = true;
+                mapped.push(Cow::Owned(new));
+            }
+            None => mapped.push(Cow::Borrowed(sct)),
+        }
+    }
+    if !changed {
+        return Ok(None);
+    }
+    let scts = mapped.iter().map(|x| x.as_ref()).collect();
+    ExtensionValue::SignedCertificateTimestamps(scts).to_vec().map(Some)
+}
+
+fn abridge_sct(sct: &[u8], dictionary: &LogDictionary) -> Result<Option<Vec<u8>>, Error> {
+    /* An SCT which already looks abridged could not be told apart from one this pass produced */
+    if sct[0] == ABRIDGED_SCT_VERSION {
+        return Err(Error::ReservedSctVersion);
+    }
+    if sct[0] != SCT_V1 || sct.len() < 1 + LOG_ID_LEN {
+        return Ok(None);
+    }
+    let Some(code) = dictionary.code_for(&sct[1..1 + LOG_ID_LEN]) else {
+        return Ok(None);
+    };
+    let mut abridged = Vec::with_capacity(sct.len() - LOG_ID_LEN + CODE_LEN);
+    abridged.push(ABRIDGED_SCT_VERSION);
+    abridged.extend_from_slice(&code.to_be_bytes());
+    abridged.extend_from_slice(&sct[1 + LOG_ID_LEN..]);
+    Ok(Some(abridged))
+}
+
+fn expand_sct(sct: &[u8], dictionary: &LogDictionary) -> Result<Option<Vec<u8>>, Error> {
+    if sct[0] != ABRIDGED_SCT_VERSION {
+        return Ok(None);
+    }
+    if sct.len() < 1 + CODE_LEN {
+        return Err(Error::MalformedExtension(SIGNED_CERTIFICATE_TIMESTAMP));
+    }
+    let code = u16::from_be_bytes([sct[1], sct[2]]);
+    let log_id = dictionary.log_id(code).ok_or(Error::UnknownLogCode(code))?;
+    let mut expanded = Vec::with_capacity(sct.len() + LOG_ID_LEN - CODE_LEN);
+    expanded.push(SCT_V1);
+    expanded.extend_from_slice(log_id);
+    expanded.extend_from_slice(&sct[1 + CODE_LEN..]);
+    Ok(Some(expanded))
+}
+
+/* Finds the elements enclosing an embedded SCT list, outermost first, ending with the OCTET
+STRING whose contents are the list:
+Certificate > TBSCertificate > [3] > Extensions > Extension > extnValue > OCTET STRING */
+fn find_embedded_list(cert: &[u8]) -> Option<Vec<Element>> {
+    let certificate = read_element(cert, 0, cert.len()).filter(|x| x.tag == 0x30 && x.contents.end == cert.len())?;
+    let tbs = children(cert, &certificate).next().filter(|x| x.tag == 0x30)?;
+    let wrapper = children(cert, &tbs).find(|x| x.tag == 0xa3)?;
+    let extensions = children(cert, &wrapper).next().filter(|x| x.tag == 0x30)?;
+    let extension = children(cert, &extensions)
+        .find(|x| x.tag == 0x30 && cert[x.contents.clone()].starts_with(SCT_LIST_OID))?;
+    let value = children(cert, &extension).find(|x| x.tag == 0x04)?;
+    let list = read_element(cert, value.contents.start, value.contents.end)
+        .filter(|x| x.tag == 0x04 && x.contents.end == value.contents.end)?;
+    Some(vec![certificate, tbs, wrapper, extensions, extension, value, list])
+}
+
+/* Replaces the contents of the innermost element with `contents`, re-encoding the length of
+every enclosing element */
+fn replace_contents(cert: &[u8], path: &[Element], contents: Vec<u8>) -> Vec<u8> {
+    let mut inner = contents;
+    let mut inner_range = path[path.len() - 1].contents.clone();
+    for element in path.iter().rev() {
+        let len = inner_range.start - element.contents.start + inner.len() + element.contents.end - inner_range.end;
+        let mut output = Vec::with_capacity(len + 6);
+        output.push(element.tag);
+        write_der_len(len, &mut output);
+        output.extend_from_slice(&cert[element.contents.start..inner_range.start]);
+        output.extend_from_slice(&inner);
+        output.extend_from_slice(&cert[inner_range.end..element.contents.end]);
+        inner = output;
+        inner_range = element.range();
+    }
+    inner
+}
+
+fn map_embedded(
+    cert: &[u8],
+    f: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, Error>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let Some(path) = find_embedded_list(cert) else {
+        return Ok(None);
+    };
+    let list = &cert[path[path.len() - 1].contents.clone()];
+    Ok(map_sct_list(list, f)?.map(|list| replace_contents(cert, &path, list)))
+}
+
+/* Rewrites the SCT lists in each entry's certificate and extensions with `f`, leaving anything
+which doesn't parse alone */
+fn map_message(
+    msg: &CertificateMessage,
+    f: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, Error> + Copy,
+    check: impl Fn(&[u8], &[u8]) -> bool,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut replacements = Vec::with_capacity(msg.certificate_entries.len());
+    for entry in &msg.certificate_entries {
+        let data = map_embedded(entry.data, f)?.filter(|data| check(entry.data, data));
+        let mut extensions_changed = false;
+        let mut extensions = entry.parse_extensions().unwrap_or_default();
+        let mut lists = Vec::new();
+        for extension in &extensions {
+            lists.push(match extension.extension_type {
+                SIGNED_CERTIFICATE_TIMESTAMP => map_sct_list(extension.data, f)?,
+                _ => None,
+            });
+        }
+        for (extension, list) in extensions.iter_mut().zip(&lists) {
+            if let Some(list) = list {
+                extension.data = list;
+                extensions_changed = true;
+            }
+        }
+        let extensions = match extensions_changed {
+            true => Some(encode_extensions(&extensions)?),
+            false => None,
+        };
+        replacements.push((data, extensions));
+    }
+    if replacements.iter().all(|(data, extensions)| data.is_none() && extensions.is_none()) {
+        return Ok(None);
+    }
+    let mut mapped = msg.clone();
+    for (entry, (data, extensions)) in mapped.certificate_entries.iter_mut().zip(&replacements) {
+        if let Some(data) = data {
+            entry.data = data;
+        }
+        if let Some(extensions) = extensions {
+            entry.extensions = extensions;
+        }
+    }
+    mapped.to_vec().map(Some)
+}
+
+#[derive(Debug)]
+pub struct Compressor {
+    dictionary: Arc<LogDictionary>,
+}
+
+impl Compressor {
+    pub fn new(dictionary: Arc<LogDictionary>) -> Self {
+        Compressor { dictionary }
+    }
+
+    pub fn dictionary(&self) -> &LogDictionary {
+        &self.dictionary
+    }
+
+    /// Abridges the SCTs in a Certificate message, which may already have been through pass 1.
+    ///
+    /// Fails with [`Error::ReservedSctVersion`] if the message holds an SCT which would be
+    /// mistaken for an abridged one.
+    pub fn compress_to_bytes(&self, cert_msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let msg = CertificateMessage::read_from_bytes(cert_msg)?;
+        let dictionary = &*self.dictionary;
+        /* Certificates are only rewritten if their DER comes back exactly, e.g. it used minimal
+        length encodings. Otherwise they are left alone, which is always lossless. */
+        let round_trips = |original: &[u8], abridged: &[u8]| {
+            matches!(map_embedded(abridged, |x| expand_sct(x, dictionary)), Ok(Some(x)) if x == original)
+        };
+        let abridged = map_message(&msg, |x| abridge_sct(x, dictionary), round_trips)?;
+        let mut output = Vec::with_capacity(LOG_DICTIONARY_ID_LEN + cert_msg.len());
+        output.extend_from_slice(&dictionary.id());
+        output.extend_from_slice(abridged.as_deref().unwrap_or(cert_msg));
+        Ok(output)
+    }
+}
+
+#[derive(Debug)]
+pub struct Decompressor {
+    dictionary: Arc<LogDictionary>,
+}
+
+impl Decompressor {
+    pub fn new(dictionary: Arc<LogDictionary>) -> Self {
+        Decompressor { dictionary }
+    }
+
+    pub fn dictionary(&self) -> &LogDictionary {
+        &self.dictionary
+    }
+
+    /// Fails with [`Error::SizeLimitExceeded`] if the expanded message would be larger than `max_size`.
+    pub fn decompress_to_bytes_with_limit(&self, compressed_msg: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+        if compressed_msg.len() < LOG_DICTIONARY_ID_LEN
+            || compressed_msg[..LOG_DICTIONARY_ID_LEN] != self.dictionary.id()
+        {
+            return Err(Error::LogDictionaryMismatch);
+        }
+        let compressed_msg = &compressed_msg[LOG_DICTIONARY_ID_LEN..];
+        let msg = CertificateMessage::read_from_bytes(compressed_msg)?;
+        let dictionary = &*self.dictionary;
+        /* Expansion only ever grows the message, so each SCT is counted before it is copied. DER
+        lengths re-encoded around embedded lists can add a few more bytes, checked at the end. */
+        if compressed_msg.len() > max_size {
+            return Err(Error::SizeLimitExceeded { limit: max_size });
+        }
+        let size = Cell::new(compressed_msg.len());
+        let expand = |sct: &[u8]| {
+            if sct[0] == ABRIDGED_SCT_VERSION {
+                size.set(size.get() + LOG_ID_LEN - CODE_LEN);
+                if size.get() > max_size {
+                    return Err(Error::SizeLimitExceeded { limit: max_size });
+                }
+            }
+            expand_sct(sct, dictionary)
+        };
+        let output = match map_message(&msg, expand, |_, _| true)? {
+            Some(expanded) => expanded,
+            None => compressed_msg.to_vec(),
+        };
+        if output.len() > max_size {
+            return Err(Error::SizeLimitExceeded { limit: max_size });
+        }
+        Ok(output)
+    }
+
+    pub fn decompress_to_bytes(&self, compressed_msg: &[u8]) -> Result<Vec<u8>, Error> {
+        self.decompress_to_bytes_with_limit(compressed_msg, usize::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::CertificateEntry;
+
+    const CORPUS: &str = "data/certificate_messages";
+
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut paths: Vec<_> = std::fs::read_dir(CORPUS).unwrap().map(|x| x.unwrap().path()).collect();
+        paths.sort();
+        paths.into_iter().map(|path| std::fs::read(path).unwrap()).collect()
+    }
+
+    /* Collects the log IDs of every SCT embedded in the corpus, standing in for a log list */
+    fn corpus_dictionary(messages: &[Vec<u8>]) -> LogDictionary {
+        let mut logs = Vec::new();
+        for msg in messages {
+            for entry in CertificateMessage::read_from_bytes(msg).unwrap().certificate_entries {
+                let Some(path) = find_embedded_list(entry.data) else {
+                    continue;
+                };
+                let extension = Extension {
+                    extension_type: SIGNED_CERTIFICATE_TIMESTAMP,
+                    data: &entry.data[path[path.len() - 1].contents.clone()],
+                };
+                let Ok(ExtensionValue::SignedCertificateTimestamps(scts)) = extension.decode() else {
+                    panic!("Malformed SCT list");
+                };
+                for sct in scts {
+                    logs.push(CtLog {
+                        log_id: sct[1..1 + LOG_ID_LEN].try_into().unwrap(),
+                        key: None,
+                        description: String::new(),
+                    });
+                }
+            }
+        }
+        LogDictionary::new(logs).unwrap()
+    }
+
+    /* A message whose single entry carries `scts` in a signed_certificate_timestamp extension */
+    fn message_with_scts(scts: &[Vec<u8>]) -> Vec<u8> {
+        let list = ExtensionValue::SignedCertificateTimestamps(scts.iter().map(Vec::as_slice).collect())
+            .to_vec()
+            .unwrap();
+        let extensions = encode_extensions(&[
+            Extension {
+                extension_type: 0x1234,
+                data: &[1, 2, 3],
+            },
+            Extension {
+                extension_type: SIGNED_CERTIFICATE_TIMESTAMP,
+                data: &list,
+            },
+        ])
+        .unwrap();
+        CertificateMessage {
+            request_context: &[],
+            certificate_entries: vec![CertificateEntry {
+                data: &[0x30, 0x03, 0x02, 0x01, 0x00],
+                extensions: &extensions,
+            }],
+        }
+        .to_vec()
+        .unwrap()
+    }
+
+    fn sct(version: u8, log_id: [u8; LOG_ID_LEN]) -> Vec<u8> {
+        let mut sct = vec![version];
+        sct.extend_from_slice(&log_id);
+        /* timestamp, empty extensions and a short signature */
+        sct.extend_from_slice(&[0, 0, 1, 0x8f, 0, 0, 0, 0, 0, 0, 0x04, 0x03, 0, 2, 0xab, 0xcd]);
+        sct
+    }
+
+    #[test]
+    fn log_lists() {
+        let keys = [b"first log key".to_vec(), b"second log key".to_vec()];
+        let log = |key: &[u8], log_id: &[u8]| {
+            format!(
+                r#"{{"description": "Test log", "log_id": "{}", "key": "{}"}}"#,
+                BASE64_STANDARD.encode(log_id),
+                BASE64_STANDARD.encode(key)
+            )
+        };
+        let list = |first: String, second: String| {
+            format!(
+                r#"{{"version": "1.0", "operators": [{{"name": "Test", "logs": [{}], "tiled_logs": [{}]}}]}}"#,
+                first, second
+            )
+        };
+        let ids: Vec<_> = keys.iter().map(Sha256::digest).collect();
+        let dictionary =
+            LogDictionary::from_log_list(&list(log(&keys[0], &ids[0]), log(&keys[1], &ids[1]))).unwrap();
+        assert_eq!(dictionary.logs().len(), 2);
+        /* Codes follow log ID order, not list order */
+        let swapped = LogDictionary::from_log_list(&list(log(&keys[1], &ids[1]), log(&keys[0], &ids[0]))).unwrap();
+        assert_eq!(dictionary.id(), swapped.id());
+        for id in &ids {
+            let code = dictionary.code_for(id).unwrap();
+            assert_eq!(swapped.code_for(id), Some(code));
+            assert_eq!(&dictionary.log_id(code).unwrap()[..], &id[..]);
+        }
+
+        assert!(matches!(
+            LogDictionary::from_log_list(&list(log(&keys[0], &ids[1]), log(&keys[1], &ids[1]))),
+            Err(Error::InvalidDictionary(_))
+        ));
+        assert!(matches!(
+            LogDictionary::from_log_list(&list(log(&keys[0], &ids[0][..16]), log(&keys[1], &ids[1]))),
+            Err(Error::InvalidDictionary(_))
+        ));
+        assert!(matches!(LogDictionary::from_log_list("{}"), Err(Error::InvalidDictionary(_))));
+    }
+
+    #[test]
+    fn embedded_scts() {
+        let messages = corpus();
+        let dictionary = Arc::new(corpus_dictionary(&messages));
+        let c = Compressor::new(dictionary.clone());
+        let d = Decompressor::new(dictionary);
+        let mut abridged = 0;
+        for msg in &messages {
+            let out = c.compress_to_bytes(msg).expect("Compression succeeds");
+            if out.len() < msg.len() {
+                abridged += 1;
+            }
+            assert!(out.len() <= msg.len() + LOG_DICTIONARY_ID_LEN);
+            assert_eq!(&d.decompress_to_bytes(&out).expect("Decompression succeeds"), msg);
+        }
+        assert!(abridged > messages.len() / 2, "Only {} messages were abridged", abridged);
+    }
+
+    #[test]
+    fn extension_scts() {
+        let known = sct(SCT_V1, [0x11; LOG_ID_LEN]);
+        let unknown = sct(SCT_V1, [0x22; LOG_ID_LEN]);
+        let dictionary = Arc::new(
+            LogDictionary::new(vec![CtLog {
+                log_id: [0x11; LOG_ID_LEN],
+                key: None,
+                description: "Known".into(),
+            }])
+            .unwrap(),
+        );
+        let c = Compressor::new(dictionary.clone());
+        let d = Decompressor::new(dictionary.clone());
+
+        let msg = message_with_scts(&[known.clone(), unknown.clone()]);
+        let out = c.compress_to_bytes(&msg).unwrap();
+        assert_eq!(out.len() + LOG_ID_LEN - CODE_LEN, msg.len() + LOG_DICTIONARY_ID_LEN);
+        assert_eq!(&out[..LOG_DICTIONARY_ID_LEN], &dictionary.id());
+        assert_eq!(d.decompress_to_bytes(&out).unwrap(), msg);
+        assert_eq!(d.decompress_to_bytes_with_limit(&out, msg.len()).unwrap(), msg);
+        for limit in [msg.len() - 1, out.len() - LOG_DICTIONARY_ID_LEN, 0] {
+            assert!(matches!(
+                d.decompress_to_bytes_with_limit(&out, limit),
+                Err(Error::SizeLimitExceeded { limit: l }) if l == limit
+            ));
+        }
+
+        /* Messages without known logs pass through unchanged */
+        let msg = message_with_scts(&[unknown]);
+        assert_eq!(&c.compress_to_bytes(&msg).unwrap()[LOG_DICTIONARY_ID_LEN..], &msg[..]);
+    }
+
+    #[test]
+    fn invalid_scts() {
+        let dictionary = Arc::new(
+            LogDictionary::new(vec![CtLog {
+                log_id: [0x11; LOG_ID_LEN],
+                key: None,
+                description: "Known".into(),
+            }])
+            .unwrap(),
+        );
+        let c = Compressor::new(dictionary.clone());
+        let d = Decompressor::new(dictionary.clone());
+
+        let msg = message_with_scts(&[sct(ABRIDGED_SCT_VERSION, [0x11; LOG_ID_LEN])]);
+        assert!(matches!(c.compress_to_bytes(&msg), Err(Error::ReservedSctVersion)));
+
+        let mut out = dictionary.id().to_vec();
+        out.extend_from_slice(&message_with_scts(&[vec![ABRIDGED_SCT_VERSION, 0, 7, 1, 2, 3]]));
+        assert!(matches!(d.decompress_to_bytes(&out), Err(Error::UnknownLogCode(7))));
+
+        let msg = message_with_scts(&[sct(SCT_V1, [0x11; LOG_ID_LEN])]);
+        let mut out = c.compress_to_bytes(&msg).unwrap();
+        out[0] ^= 1;
+        assert!(matches!(d.decompress_to_bytes(&out), Err(Error::LogDictionaryMismatch)));
+    }
+}
@@ -0,0 +1,40 @@
+/* Fixtures shared by the unit tests of several modules */
+
+/* A Certificate message with an empty request context and a single certificate without
+extensions, borrowed from https://tls13.xargs.org/#server-certificate */
+const CERTMSG: &str = "
+    0000032a0003253082032130820209a0030201020208155a92adc2048f90300d06092a86
+    4886f70d01010b05003022310b300906035504061302555331133011060355040a130a4578616d70
+    6c65204341301e170d3138313030353031333831375a170d3139313030353031333831375a302b31
+    0b3009060355040613025553311c301a060355040313136578616d706c652e756c666865696d2e6e
+    657430820122300d06092a864886f70d01010105000382010f003082010a0282010100c4803606ba
+    e7476b089404eca7b691043ff792bc19eefb7d74d7a80d001e7b4b3a4ae60fe8c071fc73e7024c0d
+    bcf4bdd11d396bba70464a13e94af83df3e10959547bc955fb412da3765211e1f3dc776caa53376e
+    ca3aecbec3aab73b31d56cb6529c8098bcc9e02818e20bf7f8a03afd1704509ece79bd9f39f1ea69
+    ec47972e830fb5ca95de95a1e60422d5eebe527954a1e7bf8a86f6466d0d9f16951a4cf7a0469259
+    5c1352f2549e5afb4ebfd77a37950144e4c026874c653e407d7d23074401f484ffd08f7a1fa05210
+    d1f4f0d5ce79702932e2cabe701fdfad6b4bb71101f44bad666a11130fe2ee829e4d029dc91cdd67
+    16dbb9061886edc1ba94210203010001a3523050300e0603551d0f0101ff0404030205a0301d0603
+    551d250416301406082b0601050507030206082b06010505070301301f0603551d23041830168014
+    894fde5bcc69e252cf3ea300dfb197b81de1c146300d06092a864886f70d01010b05000382010100
+    591645a69a2e3779e4f6dd271aba1c0bfd6cd75599b5e7c36e533eff3659084324c9e7a504079d39
+    e0d42987ffe3ebdd09c1cf1d914455870b571dd19bdf1d24f8bb9a11fe80fd592ba0398cde11e265
+    1e618ce598fa96e5372eef3d248afde17463ebbfabb8e4d1ab502a54ec0064e92f7819660d3f27cf
+    209e667fce5ae2e4ac99c7c93818f8b2510722dfed97f32e3e9349d4c66c9ea6396d744462a06b42
+    c6d5ba688eac3a017bddfc8e2cfcad27cb69d3ccdca280414465d3ae348ce0f34ab2fb9c61837131
+    2b191041641c237f11a5d65c844f0404849938712b959ed685bc5c5dd645ed19909473402926dcb4
+    0e3469a15941e8e2cca84bb6084636a00000";
+
+/// The sample Certificate message.
+pub(crate) fn certificate_message() -> Vec<u8> {
+    let mut cert_hex: String = String::from(CERTMSG);
+    cert_hex.retain(|x| !x.is_whitespace());
+    hex::decode(cert_hex).unwrap()
+}
+
+/// The single certificate in [`certificate_message`].
+pub(crate) fn certificate() -> Vec<u8> {
+    let msg = certificate_message();
+    /* Skip the context and list lengths, then the certificate's length and trailing extensions */
+    msg[7..msg.len() - 2].to_vec()
+}
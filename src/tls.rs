@@ -1,41 +1,53 @@
+//! Parsing and encoding of TLS 1.3 Certificate messages (RFC 8446 section 4.4.2).
+//!
+//! [`CertificateMessage::read_from_bytes`] splits a message into its request context and
+//! [`CertificateEntry`]s without copying. [`CertificateEntry::parse_extensions`] splits an entry's
+//! extensions into [`Extension`]s, and [`Extension::decode`] understands the `status_request` and
+//! `signed_certificate_timestamp` extensions. Every type can be written back out, and an unmodified
+//! message is reproduced byte for byte.
+
 use std::{io::Write, ops::Div};
 
-use bytes::{Buf, Bytes};
-use simple_error::SimpleError;
+use crate::Error;
 
-fn read_tls_vec<const WIDTH: u8>(value: &mut Bytes) -> Result<Bytes, SimpleError> {
+/* `end` is the offset in the whole message at which `value` finishes, so that errors can report
+where the length field started */
+fn read_tls_vec<'a, const WIDTH: u8>(
+    value: &mut &'a [u8],
+    end: usize,
+    entry: Option<usize>,
+) -> Result<&'a [u8], Error> {
     debug_assert!(WIDTH <= 4, "Invalid width specified");
+    let offset = end - value.len();
 
-    if value.len() < WIDTH.into() {
-        return Err(SimpleError::new("Not enough bytes to read length field"));
+    let width = WIDTH as usize;
+    if value.len() < width {
+        return Err(Error::TruncatedLengthField { offset, entry });
     }
+    let len = value[..width]
+        .iter()
+        .fold(0_usize, |acc, b| (acc << 8) | *b as usize);
 
-    let len: usize = value
-        .get_uint(WIDTH.into())
-        .try_into()
-        .map_err(|_| SimpleError::new("Length too large"))?;
-
-    if value.len() < len {
-        return Err(SimpleError::new("Length field longer than remaining bytes"));
+    let rest = &value[width..];
+    if rest.len() < len {
+        return Err(Error::LengthExceedsInput { offset, entry });
     }
 
-    let vec = value.split_to(len);
+    let (vec, rest) = rest.split_at(len);
+    *value = rest;
     Ok(vec)
 }
 
-fn write_tls_int<const WIDTH: u8>(
-    size: u32,
-    writer: &mut impl Write,
-) -> Result<(), Box<dyn std::error::Error>> {
+fn write_tls_int<const WIDTH: u8>(size: u32, writer: &mut impl Write) -> Result<(), Error> {
     /* Pretty ugly. Fix. */
     let u32_width = u32::BITS.div(8);
     assert!(WIDTH <= 4 && WIDTH > 0, "Invalid width specified");
     assert!(u32_width >= WIDTH.into());
-    let dropped_bytes: usize = (u32_width - Into::<u32>::into(WIDTH)).try_into()?;
+    let dropped_bytes = (u32_width - Into::<u32>::into(WIDTH)) as usize;
 
     let integer_bits: u32 = (8 * WIDTH).into();
     if Into::<u64>::into(size) > 2_u64.pow(integer_bits) - 1 {
-        return Err(Box::new(SimpleError::new("Value too large for Integer")));
+        return Err(Error::LengthOverflow);
     }
 
     let len_bytes = &size.to_be_bytes()[dropped_bytes..];
@@ -43,13 +55,10 @@ fn write_tls_int<const WIDTH: u8>(
     Ok(())
 }
 
-fn write_tls_vec<const WIDTH: u8>(
-    value: &Bytes,
-    writer: &mut impl Write,
-) -> Result<(), Box<dyn std::error::Error>> {
+fn write_tls_vec<const WIDTH: u8>(value: &[u8], writer: &mut impl Write) -> Result<(), Error> {
     debug_assert!(WIDTH <= 4 && WIDTH > 0, "Invalid width specified");
 
-    let len: u32 = value.len().try_into()?;
+    let len: u32 = value.len().try_into().map_err(|_| Error::LengthOverflow)?;
 
     write_tls_int::<WIDTH>(len, writer)?;
     writer.write_all(value)?;
@@ -80,49 +89,220 @@ fn write_tls_vec<const WIDTH: u8>(
 //     CertificateEntry certificate_list<0..2^24-1>;
 // } Certificate;
 
-#[derive(Debug)]
-pub struct CertificateEntry {
-    pub data: Bytes,
-    pub extensions: Bytes,
+// RFC 8446: 4.2
+// struct {
+//     ExtensionType extension_type;
+//     opaque extension_data<0..2^16-1>;
+// } Extension;
+
+// RFC 8446: 4.4.2.1
+// struct {
+//     CertificateStatusType status_type;   /* ocsp(1) */
+//     select (status_type) {
+//         case ocsp: OCSPResponse;         /* opaque OCSPResponse<1..2^24-1> */
+//     } response;
+// } CertificateStatus;
+
+// RFC 6962: 3.3
+// opaque SerializedSCT<1..2^16-1>;
+// struct {
+//     SerializedSCT sct_list <1..2^16-1>;
+// } SignedCertificateTimestampList;
+
+/// Carries a stapled OCSP response for the entry's certificate.
+pub const STATUS_REQUEST: u16 = 5;
+/// Carries Signed Certificate Timestamps for the entry's certificate.
+pub const SIGNED_CERTIFICATE_TIMESTAMP: u16 = 18;
+const OCSP_STATUS_TYPE: u8 = 1;
+
+/* The parsed types borrow from the message they were read from, so parsing never copies a
+certificate. Fields can be pointed at other buffers, e.g. dictionary entries, before writing. */
+
+/// One certificate from a Certificate message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertificateEntry<'a> {
+    /// The DER encoded certificate, or a SubjectPublicKeyInfo for raw public keys.
+    pub data: &'a [u8],
+    /// The entry's extension list without its length prefix, see [`CertificateEntry::parse_extensions`].
+    pub extensions: &'a [u8],
 }
 
-#[derive(Debug)]
-pub struct CertificateMessage {
-    pub request_context: Bytes,
-    pub certificate_entries: Vec<CertificateEntry>,
+/// A TLS 1.3 Certificate message, without the handshake message header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateMessage<'a> {
+    /// Empty for server certificates, otherwise it echoes the CertificateRequest's context.
+    pub request_context: &'a [u8],
+    /// The chain, leaf first.
+    pub certificate_entries: Vec<CertificateEntry<'a>>,
 }
 
-impl CertificateEntry {
-    pub fn read_from_bytes(value: &mut Bytes) -> Result<CertificateEntry, SimpleError> {
-        let data = read_tls_vec::<3>(value)?;
-        let extensions = read_tls_vec::<2>(value)?;
+impl<'a> CertificateEntry<'a> {
+    /* `end` and `index` locate `value` within the message for error reporting */
+    fn read_from_bytes(value: &mut &'a [u8], end: usize, index: usize) -> Result<Self, Error> {
+        let data = read_tls_vec::<3>(value, end, Some(index))?;
+        let extensions = read_tls_vec::<2>(value, end, Some(index))?;
         Ok(CertificateEntry { data, extensions })
     }
 
-    pub fn write_to_bytes(
-        &self,
-        writer: &mut impl Write,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        write_tls_vec::<3>(&self.data, writer)?;
-        write_tls_vec::<2>(&self.extensions, writer)?;
+    /// Writes the entry with its length prefixes.
+    pub fn write_to_bytes(&self, writer: &mut impl Write) -> Result<(), Error> {
+        write_tls_vec::<3>(self.data, writer)?;
+        write_tls_vec::<2>(self.extensions, writer)?;
         Ok(())
     }
 
-    pub fn get_size(&self) -> usize {
+    /// The number of bytes `write_to_bytes` produces.
+    pub fn encoded_len(&self) -> usize {
         3 + self.data.len() + 2 + self.extensions.len()
     }
 }
 
-impl CertificateMessage {
-    pub fn read_from_bytes(value: &mut Bytes) -> Result<CertificateMessage, SimpleError> {
-        let request_context = read_tls_vec::<1>(value)?;
-        let mut certificate_field = read_tls_vec::<3>(value)?;
+impl<'a> CertificateEntry<'a> {
+    /// Splits `extensions` into individual extensions, in order and including duplicates, so that
+    /// [`encode_extensions`] reproduces the original bytes. Error offsets are relative to the
+    /// start of `extensions`.
+    pub fn parse_extensions(&self) -> Result<Vec<Extension<'a>>, Error> {
+        let end = self.extensions.len();
+        let mut value = self.extensions;
+        let mut extensions = Vec::new();
+        while !value.is_empty() {
+            extensions.push(Extension::read_from_bytes(&mut value, end)?);
+        }
+        Ok(extensions)
+    }
+}
+
+/// A single extension from a CertificateEntry. `data` is the undecoded `extension_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extension<'a> {
+    /// The `ExtensionType` codepoint, e.g. [`STATUS_REQUEST`].
+    pub extension_type: u16,
+    /// The `extension_data`, without its length prefix.
+    pub data: &'a [u8],
+}
+
+/// The decoded `extension_data` of an extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionValue<'a> {
+    /// The DER encoded OCSPResponse from a `status_request` extension.
+    OcspResponse(&'a [u8]),
+    /// Each SerializedSCT from a `signed_certificate_timestamp` extension.
+    SignedCertificateTimestamps(Vec<&'a [u8]>),
+    /// Any other extension, or a `status_request` with a status type other than OCSP.
+    Unknown(&'a [u8]),
+}
+
+impl<'a> Extension<'a> {
+    fn read_from_bytes(value: &mut &'a [u8], end: usize) -> Result<Self, Error> {
+        if value.len() < 2 {
+            return Err(Error::TruncatedLengthField {
+                offset: end - value.len(),
+                entry: None,
+            });
+        }
+        let extension_type = u16::from_be_bytes([value[0], value[1]]);
+        *value = &value[2..];
+        let data = read_tls_vec::<2>(value, end, None)?;
+        Ok(Extension { extension_type, data })
+    }
+
+    /// Writes the extension type followed by the length prefixed data.
+    pub fn write_to_bytes(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(&self.extension_type.to_be_bytes())?;
+        write_tls_vec::<2>(self.data, writer)
+    }
+
+    /// The number of bytes `write_to_bytes` produces.
+    pub fn encoded_len(&self) -> usize {
+        2 + 2 + self.data.len()
+    }
+
+    /// Decodes `data` according to `extension_type`. Known extensions must be well formed and
+    /// use all of `data`.
+    pub fn decode(&self) -> Result<ExtensionValue<'a>, Error> {
+        let malformed = || Error::MalformedExtension(self.extension_type);
+        let end = self.data.len();
+        let mut value = self.data;
+        let decoded = match self.extension_type {
+            STATUS_REQUEST if value.first() == Some(&OCSP_STATUS_TYPE) => {
+                value = &value[1..];
+                let response = read_tls_vec::<3>(&mut value, end, None).map_err(|_| malformed())?;
+                if response.is_empty() {
+                    return Err(malformed());
+                }
+                ExtensionValue::OcspResponse(response)
+            }
+            SIGNED_CERTIFICATE_TIMESTAMP => {
+                let mut list = read_tls_vec::<2>(&mut value, end, None).map_err(|_| malformed())?;
+                let mut scts = Vec::new();
+                while !list.is_empty() {
+                    match read_tls_vec::<2>(&mut list, end, None) {
+                        Ok(sct) if !sct.is_empty() => scts.push(sct),
+                        _ => return Err(malformed()),
+                    }
+                }
+                if scts.is_empty() {
+                    return Err(malformed());
+                }
+                ExtensionValue::SignedCertificateTimestamps(scts)
+            }
+            _ => return Ok(ExtensionValue::Unknown(self.data)),
+        };
+        if !value.is_empty() {
+            return Err(malformed());
+        }
+        Ok(decoded)
+    }
+}
+
+impl ExtensionValue<'_> {
+    /// Encodes the value as `extension_data`, the inverse of [`Extension::decode`].
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        match self {
+            ExtensionValue::OcspResponse(response) => {
+                output.push(OCSP_STATUS_TYPE);
+                write_tls_vec::<3>(response, &mut output)?;
+            }
+            ExtensionValue::SignedCertificateTimestamps(scts) => {
+                let list_len: usize = scts.iter().map(|sct| 2 + sct.len()).sum();
+                write_tls_int::<2>(list_len.try_into().map_err(|_| Error::LengthOverflow)?, &mut output)?;
+                for sct in scts {
+                    write_tls_vec::<2>(sct, &mut output)?;
+                }
+            }
+            ExtensionValue::Unknown(data) => output.extend_from_slice(data),
+        }
+        Ok(output)
+    }
+}
+
+/// Encodes an extension list for [`CertificateEntry::extensions`], without its length prefix.
+pub fn encode_extensions(extensions: &[Extension]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::with_capacity(extensions.iter().map(Extension::encoded_len).sum());
+    for extension in extensions {
+        extension.write_to_bytes(&mut output)?;
+    }
+    if output.len() > u16::MAX as usize {
+        return Err(Error::LengthOverflow);
+    }
+    Ok(output)
+}
+
+impl<'a> CertificateMessage<'a> {
+    /// Parses a complete message. Trailing bytes are an error.
+    pub fn read_from_bytes(mut value: &'a [u8]) -> Result<Self, Error> {
+        let end = value.len();
+        let request_context = read_tls_vec::<1>(&mut value, end, None)?;
+        let mut certificate_field = read_tls_vec::<3>(&mut value, end, None)?;
         if !value.is_empty() {
-            return Err(SimpleError::new("Trailing data inside Certificate Message"));
+            return Err(Error::TrailingData {
+                offset: end - value.len(),
+            });
         }
         let mut certificate_entries = Vec::with_capacity(5);
         while !certificate_field.is_empty() {
-            let entry = CertificateEntry::read_from_bytes(&mut certificate_field)?;
+            let entry = CertificateEntry::read_from_bytes(&mut certificate_field, end, certificate_entries.len())?;
             certificate_entries.push(entry);
         }
         Ok(CertificateMessage {
@@ -131,28 +311,47 @@ impl CertificateMessage {
         })
     }
 
-    pub fn write_to_bytes(
-        &self,
-        writer: &mut impl Write,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let ce_size: u32 = self
-            .certificate_entries
+    fn certificate_list_len(&self) -> usize {
+        self.certificate_entries
             .iter()
-            .map(CertificateEntry::get_size)
-            .sum::<usize>()
-            .try_into()?;
-        write_tls_vec::<1>(&self.request_context, writer)?;
+            .map(CertificateEntry::encoded_len)
+            .sum()
+    }
+
+    /// The number of bytes `write_to_bytes` produces.
+    pub fn encoded_len(&self) -> usize {
+        1 + self.request_context.len() + 3 + self.certificate_list_len()
+    }
+
+    /// Writes the message, failing with [`Error::LengthOverflow`] if a field is too long to encode.
+    pub fn write_to_bytes(&self, writer: &mut impl Write) -> Result<(), Error> {
+        let ce_size: u32 = self
+            .certificate_list_len()
+            .try_into()
+            .map_err(|_| Error::LengthOverflow)?;
+        write_tls_vec::<1>(self.request_context, writer)?;
         write_tls_int::<3>(ce_size, writer)?;
         for ce in &self.certificate_entries {
             ce.write_to_bytes(writer)?;
         }
         Ok(())
     }
+
+    /// Encodes the message into a buffer allocated at exactly the right size.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::with_capacity(self.encoded_len());
+        self.write_to_bytes(&mut output)?;
+        Ok(output)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::CertificateMessage;
+    use super::{
+        encode_extensions, CertificateEntry, CertificateMessage, Extension, ExtensionValue, SIGNED_CERTIFICATE_TIMESTAMP,
+        STATUS_REQUEST,
+    };
+    use crate::Error;
 
     // Added a single byte extension field
     const CERTMSG: &str = "
@@ -183,28 +382,169 @@ mod tests {
         /* Borrowed from https://tls13.xargs.org/#server-certificate */
         let mut cert_hex: String = String::from(CERTMSG);
         cert_hex.retain(|x| !x.is_whitespace());
-        let mut cert_bytes: bytes::Bytes = hex::decode(cert_hex).unwrap().into();
-        let _ =
-            CertificateMessage::read_from_bytes(&mut cert_bytes).expect("Should correctly decode");
-        assert_eq!(cert_bytes.len(), 0, "nothing left over");
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let msg = CertificateMessage::read_from_bytes(&cert_bytes).expect("Should correctly decode");
+        assert_eq!(msg.certificate_entries.len(), 1);
+        assert_eq!(msg.certificate_entries[0].extensions, [0xff]);
+        /* The certificate is borrowed from the input rather than copied */
+        assert_eq!(msg.certificate_entries[0].data.as_ptr(), cert_bytes[7..].as_ptr());
     }
 
     #[test]
     fn round_trip() {
         let mut cert_hex: String = String::from(CERTMSG);
         cert_hex.retain(|x| !x.is_whitespace());
-        let cert_bytes: bytes::Bytes = hex::decode(cert_hex).unwrap().into();
+        let cert_bytes = hex::decode(cert_hex).unwrap();
 
-        let msg = CertificateMessage::read_from_bytes(&mut cert_bytes.clone())
-            .expect("Should correctly decode");
+        let msg = CertificateMessage::read_from_bytes(&cert_bytes).expect("Should correctly decode");
+        assert_eq!(msg.encoded_len(), cert_bytes.len());
 
         let msg_bytes: Vec<u8> = Vec::new();
         let mut cursor = std::io::Cursor::new(msg_bytes);
         msg.write_to_bytes(&mut cursor).expect("No errors");
 
-        let msg_bytes: bytes::Bytes = cursor.into_inner().into();
+        let msg_bytes = cursor.into_inner();
         assert_eq!(msg_bytes.len(), cert_bytes.len(), "nothing left over");
         assert_eq!(msg_bytes, cert_bytes);
+        assert_eq!(msg.to_vec().unwrap(), cert_bytes);
+    }
+
+    #[test]
+    fn errors_locate_the_failure() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+
+        /* Cut inside the extensions length of the only entry, which starts at offset 4 */
+        let mut truncated = cert_bytes[..cert_bytes.len() - 2].to_vec();
+        truncated[3] -= 2;
+        let err = CertificateMessage::read_from_bytes(&truncated).unwrap_err();
+        assert!(
+            matches!(err, Error::TruncatedLengthField { offset, entry: Some(0) } if offset == cert_bytes.len() - 3),
+            "{:?}",
+            err
+        );
+
+        let mut overlong = cert_bytes.clone();
+        overlong[5] += 1;
+        let err = CertificateMessage::read_from_bytes(&overlong).unwrap_err();
+        assert!(matches!(err, Error::LengthExceedsInput { offset: 4, entry: Some(0) }), "{:?}", err);
+
+        let mut trailing = cert_bytes.clone();
+        trailing.push(0);
+        let err = CertificateMessage::read_from_bytes(&trailing).unwrap_err();
+        assert!(matches!(err, Error::TrailingData { offset } if offset == cert_bytes.len()), "{:?}", err);
+
+        let err = CertificateMessage::read_from_bytes(&[0x00, 0x00]).unwrap_err();
+        assert!(matches!(err, Error::TruncatedLengthField { offset: 1, entry: None }), "{:?}", err);
+    }
+
+    #[test]
+    fn request_contexts() {
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        /* Client Certificate messages sent after the handshake echo the CertificateRequest's context */
+        for context in [&[0x2a][..], &[0xa5; 32], &[0x5a; 255]] {
+            let mut with_context = vec![context.len() as u8];
+            with_context.extend_from_slice(context);
+            with_context.extend_from_slice(&cert_bytes[1..]);
+
+            let msg = CertificateMessage::read_from_bytes(&with_context).expect("Should correctly decode");
+            assert_eq!(msg.request_context, context);
+            assert_eq!(msg.certificate_entries.len(), 1);
+            assert_eq!(msg.encoded_len(), with_context.len());
+            assert_eq!(msg.to_vec().unwrap(), with_context);
+
+            /* Offsets count the context */
+            let mut overlong = with_context.clone();
+            overlong[context.len() + 5] += 1;
+            let err = CertificateMessage::read_from_bytes(&overlong).unwrap_err();
+            assert!(
+                matches!(err, Error::LengthExceedsInput { offset, entry: Some(0) } if offset == context.len() + 4),
+                "{:?}",
+                err
+            );
+        }
+
+        let err = CertificateMessage::read_from_bytes(&[0x05, 0x01, 0x02]).unwrap_err();
+        assert!(matches!(err, Error::LengthExceedsInput { offset: 0, entry: None }), "{:?}", err);
+    }
+
+    fn sample_extensions() -> Vec<u8> {
+        let mut extensions = Vec::new();
+        /* status_request holding a 4 byte OCSP response */
+        extensions.extend_from_slice(&[0x00, 0x05, 0x00, 0x08, 0x01, 0x00, 0x00, 0x04, 0x30, 0x02, 0x0a, 0x00]);
+        /* An extension this crate doesn't know, with empty data */
+        extensions.extend_from_slice(&[0xfe, 0xed, 0x00, 0x00]);
+        /* signed_certificate_timestamp holding SCTs of 3 and 1 bytes */
+        extensions.extend_from_slice(&[0x00, 0x12, 0x00, 0x0a, 0x00, 0x08, 0x00, 0x03, 0xaa, 0xbb, 0xcc, 0x00, 0x01, 0xdd]);
+        extensions
+    }
+
+    #[test]
+    fn extensions_round_trip() {
+        let extensions = sample_extensions();
+        let entry = CertificateEntry {
+            data: &[0x30, 0x00],
+            extensions: &extensions,
+        };
+        let parsed = entry.parse_extensions().expect("Well formed");
+        let types: Vec<u16> = parsed.iter().map(|x| x.extension_type).collect();
+        assert_eq!(types, [STATUS_REQUEST, 0xfeed, SIGNED_CERTIFICATE_TIMESTAMP]);
+        assert_eq!(encode_extensions(&parsed).unwrap(), extensions);
+
+        let decoded: Vec<ExtensionValue> = parsed.iter().map(|x| x.decode().expect("Well formed")).collect();
+        assert_eq!(
+            decoded,
+            [
+                ExtensionValue::OcspResponse(&[0x30, 0x02, 0x0a, 0x00]),
+                ExtensionValue::Unknown(&[]),
+                ExtensionValue::SignedCertificateTimestamps(vec![&[0xaa, 0xbb, 0xcc], &[0xdd]]),
+            ]
+        );
+        for (extension, value) in parsed.iter().zip(&decoded) {
+            assert_eq!(value.to_vec().unwrap(), extension.data);
+        }
+
+        /* The sample message's single 0xff byte is not an extension */
+        let mut cert_hex: String = String::from(CERTMSG);
+        cert_hex.retain(|x| !x.is_whitespace());
+        let cert_bytes = hex::decode(cert_hex).unwrap();
+        let msg = CertificateMessage::read_from_bytes(&cert_bytes).unwrap();
+        let err = msg.certificate_entries[0].parse_extensions().unwrap_err();
+        assert!(matches!(err, Error::TruncatedLengthField { offset: 0, entry: None }), "{:?}", err);
+    }
+
+    #[test]
+    fn malformed_extensions() {
+        let extensions = sample_extensions();
+        /* Cut inside the last extension's data */
+        let entry = CertificateEntry {
+            data: &[],
+            extensions: &extensions[..extensions.len() - 1],
+        };
+        let err = entry.parse_extensions().unwrap_err();
+        assert!(matches!(err, Error::LengthExceedsInput { offset: 18, entry: None }), "{:?}", err);
+
+        let malformed = [
+            (STATUS_REQUEST, &[0x01, 0x00, 0x00, 0x00][..]),
+            (STATUS_REQUEST, &[0x01, 0x00, 0x00, 0x01, 0x30, 0x00]),
+            (STATUS_REQUEST, &[0x01, 0x00, 0x00, 0x02, 0x30]),
+            (SIGNED_CERTIFICATE_TIMESTAMP, &[0x00, 0x00]),
+            (SIGNED_CERTIFICATE_TIMESTAMP, &[0x00, 0x02, 0x00, 0x00]),
+            (SIGNED_CERTIFICATE_TIMESTAMP, &[0x00, 0x03, 0x00, 0x01, 0xaa, 0xbb]),
+        ];
+        for (extension_type, data) in malformed {
+            let err = Extension { extension_type, data }.decode().unwrap_err();
+            assert!(matches!(err, Error::MalformedExtension(t) if t == extension_type), "{:?}", err);
+        }
+        /* Only OCSP is defined for TLS 1.3, other status types are left alone */
+        let other = Extension {
+            extension_type: STATUS_REQUEST,
+            data: &[0x02, 0x00],
+        };
+        assert_eq!(other.decode().unwrap(), ExtensionValue::Unknown(&[0x02, 0x00]));
     }
 
     #[test]
@@ -219,23 +559,22 @@ mod tests {
 }
 
 #[cfg(test)]
-#[cfg(all(feature ="nightly-features"))]
+#[cfg(feature = "nightly-features")]
 mod datatests {
 
     #[datatest::files("data/certificate_messages", {
   input in r"^(.*)"
 })]
     fn sample_test(input: &[u8]) {
-        let mut bytes = bytes::Bytes::copy_from_slice(input);
-        let msg = super::CertificateMessage::read_from_bytes(&mut bytes)
+        let msg = super::CertificateMessage::read_from_bytes(input)
             .expect("Should correctly decode");
-        assert_eq!(bytes.len(), 0, "nothing left over");
+        assert_eq!(msg.encoded_len(), input.len(), "nothing left over");
 
         let msg_bytes: Vec<u8> = Vec::new();
         let mut cursor = std::io::Cursor::new(msg_bytes);
         msg.write_to_bytes(&mut cursor).expect("No errors");
 
-        let msg_bytes: bytes::Bytes = cursor.into_inner().into();
+        let msg_bytes = cursor.into_inner();
         assert_eq!(msg_bytes.len(), input.len(), "nothing left over");
         assert_eq!(msg_bytes, input);
     }
@@ -0,0 +1,280 @@
+/* Runs a rustls client and server against each other in memory, with generated chains whose
+intermediate is the only entry in the pass 1 dictionary. */
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use abridged_certs::pass1::{CertDictionary, LoadedDictionary};
+use abridged_certs::{Builder, Compressor, Decompressor};
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use rustls::compress::{CertCompressor, CertDecompressor, CompressionFailed, CompressionLevel, DecompressionFailed};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{CertificateCompressionAlgorithm, ClientConnection, RootCertStore, ServerConnection};
+
+const CODEPOINT: u16 = 0xfe01;
+
+/* The server's and client's leaves share an intermediate */
+struct Chain {
+    root: CertificateDer<'static>,
+    intermediate: CertificateDer<'static>,
+    leaf: CertificateDer<'static>,
+    leaf_key: PrivateKeyDer<'static>,
+    client_leaf: CertificateDer<'static>,
+    client_key: PrivateKeyDer<'static>,
+}
+
+fn ca_params(name: &str) -> CertificateParams {
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, name);
+    params
+}
+
+fn generate_chain() -> Chain {
+    let root = CertifiedIssuer::self_signed(ca_params("Loopback Root"), KeyPair::generate().unwrap()).unwrap();
+    let intermediate = CertifiedIssuer::signed_by(
+        ca_params("Loopback Intermediate"),
+        KeyPair::generate().unwrap(),
+        &root,
+    )
+    .unwrap();
+    let leaf_key = KeyPair::generate().unwrap();
+    let leaf = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&leaf_key, &intermediate)
+        .unwrap();
+    let client_key = KeyPair::generate().unwrap();
+    let mut client_params = CertificateParams::new(vec!["client.localhost".to_string()]).unwrap();
+    client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client_leaf = client_params.signed_by(&client_key, &intermediate).unwrap();
+    Chain {
+        root: root.der().clone(),
+        intermediate: intermediate.der().clone(),
+        leaf: leaf.der().clone(),
+        leaf_key: PrivatePkcs8KeyDer::from(leaf_key.serialize_der()).into(),
+        client_leaf: client_leaf.der().clone(),
+        client_key: PrivatePkcs8KeyDer::from(client_key.serialize_der()).into(),
+    }
+}
+
+/// Wraps a compressor or decompressor, keeping every uncompressed Certificate message it handles.
+#[derive(Debug)]
+struct Recording<T> {
+    inner: T,
+    messages: Mutex<Vec<Vec<u8>>>,
+}
+
+impl<T> Recording<T> {
+    fn leak(inner: T) -> &'static Self {
+        Box::leak(Box::new(Recording {
+            inner,
+            messages: Mutex::new(Vec::new()),
+        }))
+    }
+
+    fn messages(&self) -> Vec<Vec<u8>> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl CertCompressor for Recording<Compressor> {
+    fn compress(&self, input: Vec<u8>, level: CompressionLevel) -> Result<Vec<u8>, CompressionFailed> {
+        self.messages.lock().unwrap().push(input.clone());
+        self.inner.compress(input, level)
+    }
+
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertCompressor::algorithm(&self.inner)
+    }
+}
+
+impl CertDecompressor for Recording<Decompressor> {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+        self.inner.decompress(input, output)?;
+        self.messages.lock().unwrap().push(output.to_vec());
+        Ok(())
+    }
+
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertDecompressor::algorithm(&self.inner)
+    }
+}
+
+fn builder(chain: &Chain) -> Builder {
+    let dictionary: Arc<dyn CertDictionary> = Arc::new(
+        LoadedDictionary::from_entries(
+            "2024-01-01".into(),
+            vec![(vec![0xff, 0x12, 0x34], chain.intermediate.to_vec())],
+        )
+        .unwrap(),
+    );
+    Builder::new().pass1_dictionary(dictionary).codepoint(CODEPOINT)
+}
+
+fn roots(chain: &Chain) -> Arc<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    roots.add(chain.root.clone()).unwrap();
+    Arc::new(roots)
+}
+
+fn server(chain: &Chain, compressors: Vec<&'static dyn CertCompressor>) -> ServerConnection {
+    mutual_server(chain, compressors, vec![], false)
+}
+
+fn client(chain: &Chain, decompressors: Vec<&'static dyn CertDecompressor>) -> ClientConnection {
+    mutual_client(chain, vec![], decompressors, false)
+}
+
+fn mutual_server(
+    chain: &Chain,
+    compressors: Vec<&'static dyn CertCompressor>,
+    decompressors: Vec<&'static dyn CertDecompressor>,
+    client_auth: bool,
+) -> ServerConnection {
+    let config = rustls::ServerConfig::builder();
+    let config = if client_auth {
+        config.with_client_cert_verifier(WebPkiClientVerifier::builder(roots(chain)).build().unwrap())
+    } else {
+        config.with_no_client_auth()
+    };
+    let mut config = config
+        .with_single_cert(
+            vec![chain.leaf.clone(), chain.intermediate.clone()],
+            chain.leaf_key.clone_key(),
+        )
+        .unwrap();
+    config.cert_compressors = compressors;
+    config.cert_decompressors = decompressors;
+    ServerConnection::new(Arc::new(config)).unwrap()
+}
+
+fn mutual_client(
+    chain: &Chain,
+    compressors: Vec<&'static dyn CertCompressor>,
+    decompressors: Vec<&'static dyn CertDecompressor>,
+    client_auth: bool,
+) -> ClientConnection {
+    let config = rustls::ClientConfig::builder().with_root_certificates(roots(chain));
+    let mut config = if client_auth {
+        config
+            .with_client_auth_cert(
+                vec![chain.client_leaf.clone(), chain.intermediate.clone()],
+                chain.client_key.clone_key(),
+            )
+            .unwrap()
+    } else {
+        config.with_no_client_auth()
+    };
+    config.cert_compressors = compressors;
+    config.cert_decompressors = decompressors;
+    ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap()
+}
+
+/* Moves TLS records from one connection to the other until neither has anything left to send */
+fn transfer(from: &mut rustls::Connection, to: &mut rustls::Connection) -> usize {
+    let mut records = Vec::new();
+    while from.wants_write() {
+        from.write_tls(&mut records).unwrap();
+    }
+    let mut remaining = &records[..];
+    while !remaining.is_empty() {
+        to.read_tls(&mut remaining).unwrap();
+        to.process_new_packets().expect("Peer rejected our records");
+    }
+    records.len()
+}
+
+fn handshake(client: ClientConnection, server: ServerConnection) -> (rustls::Connection, rustls::Connection) {
+    let mut client = rustls::Connection::from(client);
+    let mut server = rustls::Connection::from(server);
+    for _ in 0..10 {
+        if !client.is_handshaking() && !server.is_handshaking() {
+            return (client, server);
+        }
+        let sent = transfer(&mut client, &mut server) + transfer(&mut server, &mut client);
+        assert!(sent > 0, "Handshake stalled");
+    }
+    panic!("Handshake did not complete");
+}
+
+#[test]
+fn abridged_handshake() {
+    let chain = generate_chain();
+    let builder = builder(&chain);
+    let compressor = Recording::leak(builder.build_compressor().unwrap());
+    let decompressor = Recording::leak(builder.build_decompressor().unwrap());
+
+    let (mut client, mut server) = handshake(client(&chain, vec![decompressor]), server(&chain, vec![compressor]));
+
+    /* The server compressed exactly one message and the client decompressed it unchanged */
+    let sent = compressor.messages();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(decompressor.messages(), sent);
+    /* Pass 1 replaced the intermediate with its 3 byte identifier before pass 2 ran */
+    let compressed = builder.build_compressor().unwrap().compress_to_bytes(&sent[0]).unwrap();
+    let pass1_only = builder.clone().without_pass2().build_compressor().unwrap();
+    let abridged = pass1_only.compress_to_bytes(&sent[0]).unwrap();
+    assert_eq!(abridged.len() + chain.intermediate.len() - 3, sent[0].len());
+    assert!(compressed.len() < abridged.len());
+
+    let peer_chain = client.peer_certificates().expect("Server sent a chain");
+    assert_eq!(peer_chain, [chain.leaf.clone(), chain.intermediate.clone()]);
+
+    server.writer().write_all(b"Hello from the server").unwrap();
+    transfer(&mut server, &mut client);
+    let mut received = [0; 64];
+    let len = client.reader().read(&mut received).unwrap();
+    assert_eq!(&received[..len], b"Hello from the server");
+}
+
+#[test]
+fn mutual_tls() {
+    let chain = generate_chain();
+    let builder = builder(&chain);
+    let server_compressor = Recording::leak(builder.build_compressor().unwrap());
+    let server_decompressor = Recording::leak(builder.build_decompressor().unwrap());
+    let client_compressor = Recording::leak(builder.build_compressor().unwrap());
+    let client_decompressor = Recording::leak(builder.build_decompressor().unwrap());
+
+    let (client, server) = handshake(
+        mutual_client(&chain, vec![client_compressor], vec![client_decompressor], true),
+        mutual_server(&chain, vec![server_compressor], vec![server_decompressor], true),
+    );
+
+    /* Each direction went through the abridged algorithm once */
+    assert_eq!(server_compressor.messages().len(), 1);
+    assert_eq!(client_decompressor.messages(), server_compressor.messages());
+    let sent = client_compressor.messages();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(server_decompressor.messages(), sent);
+    /* rustls only supports client authentication during the handshake, where the context is empty */
+    assert_eq!(sent[0][0], 0);
+    let pass1_only = builder.without_pass2().build_compressor().unwrap();
+    let abridged = pass1_only.compress_to_bytes(&sent[0]).unwrap();
+    assert_eq!(abridged.len() + chain.intermediate.len() - 3, sent[0].len());
+
+    assert_eq!(
+        server.peer_certificates().expect("Client sent a chain"),
+        [chain.client_leaf.clone(), chain.intermediate.clone()]
+    );
+    assert_eq!(
+        client.peer_certificates().expect("Server sent a chain"),
+        [chain.leaf.clone(), chain.intermediate.clone()]
+    );
+}
+
+#[test]
+fn falls_back_without_a_shared_codepoint() {
+    let chain = generate_chain();
+    let compressor = Recording::leak(builder(&chain).build_compressor().unwrap());
+    let decompressor = Recording::leak(builder(&chain).codepoint(CODEPOINT + 1).build_decompressor().unwrap());
+
+    let (client, _) = handshake(client(&chain, vec![decompressor]), server(&chain, vec![compressor]));
+
+    assert!(compressor.messages().is_empty());
+    assert!(decompressor.messages().is_empty());
+    assert_eq!(client.peer_certificates().unwrap().len(), 2);
+}